@@ -113,7 +113,7 @@ function syntax_highlighter(parent_element, text_content, symbols) {
     for(var i = 0;i < lines.length;i ++) {
         var type_flag = "";
         var whitespcae_flag = 0;
-        var class_flag = (i === current_error_line ? "synhi-err" : "");
+        var class_flag = (current_error_lines.includes(i) ? "synhi-err" : "");
         var symbol_flag = false;
         var running = "";
         for(var j = 0;j < lines[i].length;j ++) {
@@ -129,7 +129,7 @@ function syntax_highlighter(parent_element, text_content, symbols) {
                 add_textarea_span(parent_element, running, class_flag);
                 running = "";
                 whitespcae_flag = 0;
-                class_flag = (i === current_error_line ? "synhi-err" : "");
+                class_flag = (current_error_lines.includes(i) ? "synhi-err" : "");
             }
             if(/\s/.test(char)) {//is whitespace?
                 whitespcae_flag = 1;
@@ -151,7 +151,7 @@ function syntax_highlighter(parent_element, text_content, symbols) {
                 add_textarea_span(parent_element, running, class_flag);
                 running = "";
                 whitespcae_flag = 0;
-                class_flag = (i === current_error_line ? "synhi-err" : "");
+                class_flag = (current_error_lines.includes(i) ? "synhi-err" : "");
             }
             if(char === "#"){
                 add_textarea_span(parent_element, lines[i].substring(j), "synhi-comment");
@@ -166,7 +166,7 @@ function syntax_highlighter(parent_element, text_content, symbols) {
                 add_textarea_span(parent_element, running, class_flag);
                 running = "";
                 whitespcae_flag = false;
-                class_flag = (i === current_error_line ? "synhi-err" : "");
+                class_flag = (current_error_lines.includes(i) ? "synhi-err" : "");
             }
 
             var end_flag =  j == lines[i].length - 1 || /\s/.test(lines[i][j + 1]);
@@ -177,7 +177,7 @@ function syntax_highlighter(parent_element, text_content, symbols) {
                 add_textarea_span(parent_element, running, class_flag);
                 running = "";
                 whitespcae_flag = false;
-                class_flag = (i === current_error_line ? "synhi-err" : "");
+                class_flag = (current_error_lines.includes(i) ? "synhi-err" : "");
             }
 
             if(temp == "feature" || temp == "switch" || temp == "symbol" || temp == "rule" || temp == "diacritic" || temp == "root" || temp == "all" || temp == "sub"
@@ -188,7 +188,7 @@ function syntax_highlighter(parent_element, text_content, symbols) {
                 add_textarea_span(parent_element, running, class_flag);
                 running = "";
                 whitespcae_flag = false;
-                class_flag = (i === current_error_line ? "synhi-err" : "");
+                class_flag = (current_error_lines.includes(i) ? "synhi-err" : "");
             }
 
             if(temp == "=>" || temp == "/" || temp == "//" || temp == "_" || temp == "*" || temp == "$" || temp == "+" || temp == "?") {
@@ -196,7 +196,7 @@ function syntax_highlighter(parent_element, text_content, symbols) {
                 add_textarea_span(parent_element, running, class_flag);
                 running = "";
                 whitespcae_flag = false;
-                class_flag = (i === current_error_line ? "synhi-err" : "");
+                class_flag = (current_error_lines.includes(i) ? "synhi-err" : "");
             }
 
             if(symbols.includes(temp) && type_flag != "rule") {
@@ -204,7 +204,7 @@ function syntax_highlighter(parent_element, text_content, symbols) {
                 add_textarea_span(parent_element, running, class_flag);
                 running = "";
                 whitespcae_flag = false;
-                class_flag = (i === current_error_line ? "synhi-err" : "");
+                class_flag = (current_error_lines.includes(i) ? "synhi-err" : "");
             }
 
             if(temp === "symbol" || temp === "diacritic") {
@@ -228,7 +228,7 @@ function syntax_highlighter(parent_element, text_content, symbols) {
     }
 }
 
-current_error_line = -1;
+current_error_lines = [];
 
 //For documentation and webapp
 //Util method of syntax_highlighter()
@@ -30,8 +30,12 @@ function create_socket() {
             load_save_state(obj.LoadFileResult.data);
         } else if(Object.hasOwn(obj, 'RunSCResult')) {
             handle_sc_response(obj.RunSCResult.to_convert);
+        } else if(Object.hasOwn(obj, 'TraceSCResult')) {
+            handle_trace_response(obj.TraceSCResult.steps);
+        } else if(Object.hasOwn(obj, 'ReverseSCResult')) {
+            handle_reverse_response(obj.ReverseSCResult.candidates);
         } else if(Object.hasOwn(obj, 'CompilationResult')) {
-            handle_comp_response(obj.CompilationResult.result);
+            handle_comp_response(obj.CompilationResult.errors);
         } else if(obj == "RequestOverwrite") {
             if(confirm("Overwrite file?")) {
                 post_message({SaveFile: {file_path: document.getElementById("save-file-location").value, data: get_state_for_save(), overwrite: true}});
@@ -136,6 +140,14 @@ function send_sc_request(array, program) {
     post_message({ RunSC: { program_name: program, to_convert: array }});
 }
 
+function send_trace_request(word, program) {
+    post_message({ TraceSC: { program_name: program, word: word }});
+}
+
+function send_reverse_request(word, program) {
+    post_message({ ReverseSC: { program_name: program, word: word, max_candidates: 20 }});
+}
+
 function try_compile(program) {
     try {
         post_message({ TryCompile: { program: program }});
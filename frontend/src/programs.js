@@ -29,7 +29,8 @@ var current_try_compilation_contents = "";
 var current_try_compilation_contents_counter = 0;
 var current_try_compilation_counter = 0;
 var current_try_compilation_response_counter = 0;
-var current_error_line = -1;
+//The lines of the program, counted from 0, that have compilation errors
+var current_error_lines = [];
 
 function try_try_compile() {
     if(current_try_compilation_counter === current_try_compilation_response_counter && current_try_compilation_contents_counter > current_try_compilation_counter) {
@@ -38,22 +39,38 @@ function try_try_compile() {
     }
 }
 
-function handle_comp_response(response) {
+function describe_comp_error(error) {
+    var str = error.error_type + " - " + error.error_message + ", line #" + error.line_number_user_program.Raw;
+    if(error.column_span !== null) {
+        str += ", columns " + (error.column_span[0] + 1) + "-" + error.column_span[1];
+    }
+    if(error.file !== null) {
+        str += " of " + error.file;
+    }
+    return str;
+}
+
+function handle_comp_response(errors) {
     var element = document.getElementById("compilation-status");
-    var prev_error_line = current_error_line;
-    if(response === null) {
+    var prev_error_lines = current_error_lines.join();
+    if(errors.length == 0) {
         element.style.color = "green";
         element.textContent = "Compilation Status: Compilation Success";
-        current_error_line = -1;
+        element.title = "";
+        current_error_lines = [];
     } else {
         element.style.color = "red";
-        var str = "Compilation Status: ";
-        str += response.error_type + " - " + response.error_message + ", line #" + response.line_number_user_program.Raw;
-        current_error_line = response.line_number_user_program.Raw - 1;
-        
+        var str = "Compilation Status: " + describe_comp_error(errors[0]);
+        if(errors.length > 1) {
+            str += " (and " + (errors.length - 1) + " more)";
+        }
+        //Errors in included files don't have a line in this program to mark
+        current_error_lines = errors.filter(e => e.file === null).map(e => e.line_number_user_program.Raw - 1);
+
         element.textContent = str;
+        element.title = errors.map(describe_comp_error).join("\n");
     }
-    if(prev_error_line != current_error_line) update_textarea(false);
+    if(prev_error_lines != current_error_lines.join()) update_textarea(false);
     current_try_compilation_response_counter++;
     try_try_compile();
 }
@@ -158,7 +175,73 @@ function handle_program_manager_save() {
     send_test_words_flag = name == "" ? "test" : name;
 }
 
+//The word to trace once the program compiles, and the word currently shown in the trace panel
+var send_trace_word = "";
+var traced_word = "";
+//The word to find ancestors of once the program compiles
+var send_reverse_word = "";
+
+//The word on the line the cursor is on in the test area
+function get_test_area_cursor_word() {
+    var element = document.getElementById("program-manager-test-area");
+    var line = element.value.substring(0, element.selectionStart).split("\n").length - 1;
+    return element.value.split("\n")[line].split("=>")[0].trim();
+}
+
+function handle_program_manager_trace() {
+    var word = get_test_area_cursor_word();
+    if(word == "") return;
+
+    handle_program_manager_run();
+    send_trace_word = word;
+}
+
+function handle_program_manager_reverse() {
+    var word = get_test_area_cursor_word();
+    if(word == "") return;
+
+    handle_program_manager_run();
+    send_reverse_word = word;
+}
+
+function handle_trace_response(steps) {
+    var str = traced_word + "\n";
+    for(const step of steps) {
+        str += "line " + step.line_number + "\t" + step.rule + "\t" + step.result + "\n";
+    }
+    str += "(click to close)";
+
+    var element = document.getElementById("program-manager-trace");
+    element.textContent = str;
+    element.style.display = "block";
+}
+
+function handle_reverse_response(candidates) {
+    var str = "Ancestors of " + traced_word + "\n";
+    for(const candidate of candidates) {
+        str += candidate + "\n";
+    }
+    if(candidates.length == 0) {
+        str += "(none found)\n";
+    }
+    str += "(click to close)";
+
+    var element = document.getElementById("program-manager-trace");
+    element.textContent = str;
+    element.style.display = "block";
+}
+
 function handle_program_area_compile_success() {
+    if(send_trace_word != "") {
+        send_trace_request(send_trace_word, send_test_words_flag);
+        traced_word = send_trace_word;
+        send_trace_word = "";
+    }
+    if(send_reverse_word != "") {
+        send_reverse_request(send_reverse_word, send_test_words_flag);
+        traced_word = send_reverse_word;
+        send_reverse_word = "";
+    }
     if(send_test_words_flag != "") {
         var temp = document.getElementById("program-manager-test-area").value.split("\n");
         for(var i = 0;i < temp.length;i ++) {
@@ -265,4 +348,7 @@ document.getElementById("program-manager-button-exit").addEventListener("mousedo
 
 document.getElementById("program-manager-button-save").addEventListener("mousedown", () => handle_program_manager_save());
 document.getElementById("program-manager-button-comprun").addEventListener("mousedown", () => handle_program_manager_run());
+document.getElementById("program-manager-button-trace").addEventListener("mousedown", () => handle_program_manager_trace());
+document.getElementById("program-manager-button-reverse").addEventListener("mousedown", () => handle_program_manager_reverse());
+document.getElementById("program-manager-trace").addEventListener("mousedown", function() { this.style.display = "none"; });
 document.getElementById("programs-selector").addEventListener("input", () => handle_program_manager_selection_change());
\ No newline at end of file
@@ -13,6 +13,42 @@ pub enum LexicaMode {
     WebIO,
     /// Use the manual mode
     Manual(ManualCommand),
+    /// Show every rule that changes a word
+    Trace(TraceCommand),
+    /// List every error and likely mistake in a sound change program
+    Check(CheckCommand),
+    /// Rewrite a sound change program in the standard layout
+    Format(FormatCommand),
+}
+
+#[derive(Debug, Args)]
+pub struct TraceCommand {
+    /// The path to the sound change program
+    pub program: String,
+    /// The word to trace
+    pub word: String,
+    /// Read the word and write the result in the program's spelling, using its deromanizer
+    /// and romanizer
+    #[arg(long)]
+    pub spelling: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct CheckCommand {
+    /// The path to the sound change program
+    pub program: String,
+    /// Fail if there are any warnings, not just if the program doesn't construct
+    #[arg(long)]
+    pub deny_warnings: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct FormatCommand {
+    /// The path to the sound change program
+    pub program: String,
+    /// Only report whether the program is already formatted, without changing it
+    #[arg(long)]
+    pub check: bool,
 }
 
 #[derive(Debug, Args)]
@@ -26,7 +62,7 @@ pub struct ManualCommand {
 
 #[derive(Debug, Subcommand)]
 pub enum ManualSubcommand {
-    Rebuild(ManualRebuild)
+    Rebuild(ManualRebuild),
 }
 
 #[derive(Debug, Args)]
@@ -34,4 +70,4 @@ pub struct ManualRebuild {
     /// The table ID to start at
     #[arg(short, long, default_value_t = 0)]
     pub start: u16,
-}
\ No newline at end of file
+}
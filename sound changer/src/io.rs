@@ -195,10 +195,25 @@ fn web_socket_loop(mut websocket: WebSocket<TcpStream>) {
             WebSocketMessage::TryCompile { program: _ } => {
                 println!("Handled try compile message in: {:.2?}", elapsed)
             }
+            WebSocketMessage::LintProgram { program: _ } => {
+                println!("Handled lint program message in: {:.2?}", elapsed)
+            }
             WebSocketMessage::RunSC {
                 program_name: _,
                 to_convert: _,
+                spelling: _,
             } => println!("Handled run sound changer message in: {:.2?}", elapsed),
+            WebSocketMessage::TraceSC {
+                program_name: _,
+                word: _,
+                spelling: _,
+            } => println!("Handled trace sound changer message in: {:.2?}", elapsed),
+            WebSocketMessage::ReverseSC {
+                program_name: _,
+                word: _,
+                max_candidates: _,
+                spelling: _,
+            } => println!("Handled reverse sound changer message in: {:.2?}", elapsed),
             WebSocketMessage::Unknown { error: _ } => {
                 println!("Handled unknown message in: {:.2?}", elapsed)
             }
@@ -4,6 +4,7 @@ use std::time::Instant;
 
 use manual_ux::project::load_project;
 
+extern crate bincode;
 extern crate clap;
 extern crate fancy_regex;
 extern crate no_panic;
@@ -26,18 +27,122 @@ fn main() {
 
     let args = args::LexicaArgs::parse();
 
-    match args.mode {
-        args::LexicaMode::WebIO => io::web_socket_listener(),
+    //Commands that can fail give back whether they succeeded, for the exit code
+    let success = match args.mode {
+        args::LexicaMode::WebIO => {
+            io::web_socket_listener();
+            true
+        }
         args::LexicaMode::Manual(command) => match command.command {
-            args::ManualSubcommand::Rebuild(v) => manual_ux::rebuilder::rebuild(
-                &mut load_project(command.path.clone()).unwrap(),
-                v.start,
-                command.path,
-                true,
-            ),
+            args::ManualSubcommand::Rebuild(v) => {
+                manual_ux::rebuilder::rebuild(
+                    &mut load_project(command.path.clone()).unwrap(),
+                    v.start,
+                    command.path,
+                    true,
+                );
+                true
+            }
         },
-    }
+        args::LexicaMode::Trace(command) => {
+            trace(&command);
+            true
+        }
+        args::LexicaMode::Check(command) => check(&command),
+        args::LexicaMode::Format(command) => format(&command),
+    };
 
     let elapsed = start.elapsed();
-    println!("Total runtime: {:?}", elapsed)
+    println!("Total runtime: {:?}", elapsed);
+    if !success {
+        std::process::exit(1);
+    }
+}
+
+fn trace(command: &args::TraceCommand) {
+    use sc::constructor::construct_with_path;
+
+    let contents = match io::load_from_file(&command.program, false) {
+        Ok(v) => v,
+        Err(v) => return println!("{}", v.get_message()),
+    };
+    let program = match construct_with_path(&contents, &command.program) {
+        Ok(v) => v,
+        Err(v) => return println!("{}", v),
+    };
+
+    let result = program
+        .read_word(&command.word, command.spelling)
+        .and_then(|v| program.apply_traced(v))
+        .and_then(|(word, steps)| Ok((program.write_word(word, command.spelling)?, steps)));
+    match result {
+        Ok((word, steps)) => {
+            println!("{}", command.word);
+            for step in steps {
+                println!(
+                    "  line {}\t{}\t{}",
+                    step.line_number, step.rule, step.result
+                );
+            }
+            println!("=> {}", word);
+        }
+        Err(v) => println!("{}", v),
+    }
+}
+
+/// Lints the program. Fails if it can't be loaded or constructed, or with `--deny-warnings`
+/// if there are any warnings.
+fn check(command: &args::CheckCommand) -> bool {
+    use sc::constructor::construct_recovering;
+
+    let contents = match io::load_from_file(&command.program, false) {
+        Ok(v) => v,
+        Err(v) => {
+            eprintln!("{}", v.get_message());
+            return false;
+        }
+    };
+    let warnings = match construct_recovering(&contents, &command.program) {
+        Ok(v) => v.lint(),
+        Err(errors) => {
+            for e in errors {
+                eprintln!("{}", e);
+            }
+            return false;
+        }
+    };
+
+    for warning in &warnings {
+        println!("{}", warning);
+    }
+    println!("{} warning(s)", warnings.len());
+    warnings.is_empty() || !command.deny_warnings
+}
+
+/// Formats the program in place. Fails if it can't be loaded or saved, or with `--check` if
+/// it isn't already formatted.
+fn format(command: &args::FormatCommand) -> bool {
+    use sc::formatter::format_program;
+
+    let contents = match io::load_from_file(&command.program, false) {
+        Ok(v) => v,
+        Err(v) => {
+            eprintln!("{}", v.get_message());
+            return false;
+        }
+    };
+    let formatted = format_program(&contents);
+    if formatted == contents {
+        println!("{} is already formatted", command.program);
+        return true;
+    }
+    if command.check {
+        eprintln!("{} is not formatted", command.program);
+        return false;
+    }
+    if let Some(v) = io::save_to_file(&command.program, &formatted, true, false) {
+        eprintln!("{}", v.get_message());
+        return false;
+    }
+    true
 }
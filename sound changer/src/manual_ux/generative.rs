@@ -106,6 +106,8 @@ pub enum RuntimeErrorType {
     TableNotFound,
     EnumNotFound,
     SoundChangeCompileError,
+    /// A rule named in the exceptions of `soundchangeexcept` isn't in the program.
+    UnknownSoundChangeRule(String),
     CompileError(CompileErrorType),
     IOError(IOError),
 }
@@ -541,6 +541,23 @@ fn parse_access(
                     )],
                 ))),
             )),
+            Keyword::SoundChangeExcept => Ok((
+                FunctionType::SoundChangeExcept,
+                Some(ParsingContext::AwaitingFunctionBracket(VecDeque::from(
+                    vec![
+                        DataTypeDescriptor::TableDataType(TableDataTypeDescriptor::String),
+                        DataTypeDescriptor::TableDataType(TableDataTypeDescriptor::String),
+                    ],
+                ))),
+            )),
+            Keyword::SoundChangeSpelled => Ok((
+                FunctionType::SoundChangeSpelled,
+                Some(ParsingContext::AwaitingFunctionBracket(VecDeque::from(
+                    vec![DataTypeDescriptor::TableDataType(
+                        TableDataTypeDescriptor::String,
+                    )],
+                ))),
+            )),
             Keyword::Mutate => Ok((
                 FunctionType::Mutate,
                 Some(ParsingContext::AwaitingFunctionBracket(VecDeque::from(
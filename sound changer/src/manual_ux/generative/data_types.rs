@@ -16,6 +16,8 @@ pub enum Keyword {
     Save,
     Saved,
     SoundChange,
+    SoundChangeExcept,
+    SoundChangeSpelled,
     Mutate,
     Enum,
     String,
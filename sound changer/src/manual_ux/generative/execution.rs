@@ -1,10 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{
-    io,
-    manual_ux::project::Project,
-    sc::{applicator::from_string, constructor::construct, data::to_string},
-};
+use crate::{io, manual_ux::project::Project, sc::cache::construct_cached};
 
 use super::super::table::*;
 use super::*;
@@ -31,6 +27,12 @@ pub enum StringNode {
     AdditionNode(Box<StringNode>, Box<StringNode>),
     ConversionNode(RangeNode),
     SoundChangeNode(Box<StringNode>, Box<StringNode>),
+    /// Like a sound change node, but the last node lists rule names to skip, separated by
+    /// spaces. It can have one value for every word or one value per word.
+    SoundChangeExceptNode(Box<StringNode>, Box<StringNode>, Box<StringNode>),
+    /// Like a sound change node, but the words are read and written in the program's spelling
+    /// using its `deromanizer` and `romanizer` sections.
+    SoundChangeSpelledNode(Box<StringNode>, Box<StringNode>),
 }
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
@@ -317,7 +319,34 @@ impl StringNode {
                 load_program_if_not_loaded(&program_name, context)?;
                 let inputs = source.eval(context)?;
 
-                Ok(apply_sc(&program_name, inputs, context)?)
+                Ok(apply_sc(
+                    &program_name,
+                    inputs,
+                    vec![String::new()],
+                    false,
+                    context,
+                )?)
+            }
+            StringNode::SoundChangeExceptNode(source, program, exceptions) => {
+                let program_name = enforce_single(program.eval(context)?)?;
+                load_program_if_not_loaded(&program_name, context)?;
+                let inputs = source.eval(context)?;
+                let exceptions = exceptions.eval(context)?;
+
+                Ok(apply_sc(&program_name, inputs, exceptions, false, context)?)
+            }
+            StringNode::SoundChangeSpelledNode(source, program) => {
+                let program_name = enforce_single(program.eval(context)?)?;
+                load_program_if_not_loaded(&program_name, context)?;
+                let inputs = source.eval(context)?;
+
+                Ok(apply_sc(
+                    &program_name,
+                    inputs,
+                    vec![String::new()],
+                    true,
+                    context,
+                )?)
             }
         }
     }
@@ -650,7 +679,7 @@ fn load_program_if_not_loaded(
         // TODO: Pass along error information
         Err(err) => return runtime_err(RuntimeErrorType::IOError(err)),
     };
-    let program = match construct(&contents) {
+    let program = match construct_cached(&contents, path_str) {
         Ok(v) => v,
         // TODO: Pass along error information
         Err(_) => return runtime_err(RuntimeErrorType::SoundChangeCompileError),
@@ -665,16 +694,30 @@ fn load_program_if_not_loaded(
 fn apply_sc(
     program_name: &String,
     inputs: Vec<String>,
+    exceptions: Vec<String>,
+    spelling: bool,
     context: &mut ExecutionContext,
 ) -> Result<Vec<String>, GenerativeProgramRuntimeError> {
+    if exceptions.len() != 1 && exceptions.len() != inputs.len() {
+        return runtime_err(RuntimeErrorType::MismatchedRangeLengths);
+    }
     let program = context.project.programs.get(program_name).unwrap();
     let mut results = Vec::with_capacity(inputs.len());
 
-    for input in inputs {
-        let converted_string = from_string(program, &input).unwrap();
-        let changed_word = program.apply(converted_string).unwrap();
-        let result = to_string(program, changed_word).unwrap();
-        results.push(result);
+    for (i, input) in inputs.iter().enumerate() {
+        let skipped: Vec<&str> = exceptions[i.min(exceptions.len() - 1)]
+            .split_whitespace()
+            .collect();
+        if let Some(name) = skipped.iter().find(|v| !program.has_rule(v)) {
+            return runtime_err(RuntimeErrorType::UnknownSoundChangeRule(String::from(
+                *name,
+            )));
+        }
+        let converted_string = program.read_word(input, spelling).unwrap();
+        let changed_word = program
+            .apply_with_exceptions(converted_string, &skipped)
+            .unwrap();
+        results.push(program.write_word(changed_word, spelling).unwrap());
     }
 
     Ok(results)
@@ -42,6 +42,8 @@ pub enum FunctionType {
     Save,
     Saved,
     SoundChange,
+    SoundChangeExcept,
+    SoundChangeSpelled,
     Mutate,
     SymbolLookup(String),
 }
@@ -368,6 +370,34 @@ impl BuilderNode {
                     Box::new(program),
                 )))
             }
+            BuilderNode::CombinationNode(FunctionType::SoundChangeExcept, v, _) => {
+                if v.len() != 3 {
+                    panic!()
+                }
+
+                let source = v[0].clone().try_convert_string(context)?;
+                let program = v[1].clone().try_convert_string(context)?;
+                let exceptions = v[2].clone().try_convert_string(context)?;
+
+                Ok(TypedNode::StringNode(StringNode::SoundChangeExceptNode(
+                    Box::new(source),
+                    Box::new(program),
+                    Box::new(exceptions),
+                )))
+            }
+            BuilderNode::CombinationNode(FunctionType::SoundChangeSpelled, v, _) => {
+                if v.len() != 2 {
+                    panic!()
+                }
+
+                let source = v[0].clone().try_convert_string(context)?;
+                let program = v[1].clone().try_convert_string(context)?;
+
+                Ok(TypedNode::StringNode(StringNode::SoundChangeSpelledNode(
+                    Box::new(source),
+                    Box::new(program),
+                )))
+            }
             BuilderNode::CombinationNode(FunctionType::Mutate, v, _) => {
                 if v.len() != 3 {
                     panic!()
@@ -636,6 +666,8 @@ impl FunctionType {
             | FunctionType::Save
             | FunctionType::Saved
             | FunctionType::SoundChange
+            | FunctionType::SoundChangeExcept
+            | FunctionType::SoundChangeSpelled
             | FunctionType::Mutate
             | FunctionType::SymbolLookup(_) => true,
             FunctionType::Addition | FunctionType::Subtraction => false,
@@ -648,6 +680,8 @@ impl FunctionType {
             | FunctionType::Filter
             | FunctionType::Save
             | FunctionType::SoundChange
+            | FunctionType::SoundChangeExcept
+            | FunctionType::SoundChangeSpelled
             | FunctionType::SymbolLookup(_)
             | FunctionType::Mutate => FinalOperandIndex::First,
             FunctionType::Addition | FunctionType::Subtraction => FinalOperandIndex::Last,
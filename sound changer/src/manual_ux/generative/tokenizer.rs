@@ -250,6 +250,20 @@ const TOKENS: &[TokenDefinition] = &[
         match_mode: MatchMode::Keyword,
         ..BASE_TOKEN
     },
+    TokenDefinition {
+        token_type: TokenType::Keyword(Keyword::SoundChangeExcept),
+        descriptor: "soundchangeexcept",
+        priority: 2,
+        match_mode: MatchMode::Keyword,
+        ..BASE_TOKEN
+    },
+    TokenDefinition {
+        token_type: TokenType::Keyword(Keyword::SoundChangeSpelled),
+        descriptor: "soundchangespelled",
+        priority: 2,
+        match_mode: MatchMode::Keyword,
+        ..BASE_TOKEN
+    },
     TokenDefinition {
         token_type: TokenType::Keyword(Keyword::Mutate),
         descriptor: "mutate",
@@ -147,6 +147,25 @@ fn test_int_3() {
     int_test(PATH_STR);
 }
 
+#[test]
+fn test_int_4() {
+    const PATH_STR: &str = "test-data/backend/4";
+    int_test(PATH_STR);
+}
+
+#[test]
+#[should_panic(expected = "UnknownSoundChangeRule")]
+fn test_int_5() {
+    const PATH_STR: &str = "test-data/backend/5";
+    int_test(PATH_STR);
+}
+
+#[test]
+fn test_int_6() {
+    const PATH_STR: &str = "test-data/backend/6";
+    int_test(PATH_STR);
+}
+
 #[test]
 fn table_header_test_1() {
     const TEST_HEADER: &str = "";
@@ -2,10 +2,159 @@ use super::data::*;
 
 impl super::data::Program {
     pub fn apply(&self, input: Word) -> std::result::Result<Word, ApplicationError> {
+        self.apply_with_exceptions(input, &[])
+    }
+
+    /// Applies the program, skipping every rule named in `exceptions`. Used for words that
+    /// didn't undergo some changes, like borrowings or onomatopoeia.
+    pub fn apply_with_exceptions(
+        &self,
+        input: Word,
+        exceptions: &[&str],
+    ) -> std::result::Result<Word, ApplicationError> {
+        //A misspelled name would otherwise quietly skip nothing
+        if let Some(name) = exceptions.iter().find(|v| !self.has_rule(v)) {
+            return Err(ApplicationError::InternalError(format!(
+                "Rule not found: \"{}\"",
+                name
+            )));
+        }
         let mut context: ExecutionContext = create_execution_context(&input);
+        context.exceptions = exceptions.iter().map(|v| String::from(*v)).collect();
+        self.run(&mut context)?;
+        Ok(context.result)
+    }
+
+    /// Whether a rule with this name is in the program or one of its subroutines.
+    pub fn has_rule(&self, name: &str) -> bool {
+        self.rules
+            .iter()
+            .chain(self.subroutines.values().flatten())
+            .any(|v| matches!(v, Rule::TransformationRule { name: n, .. } if n == name))
+    }
+
+    /// Applies the program and returns the word at every `stage` marker it passed, in the
+    /// order they were reached.
+    pub fn apply_with_stages(
+        &self,
+        input: Word,
+    ) -> std::result::Result<Vec<(String, Word)>, ApplicationError> {
+        let mut context: ExecutionContext = create_execution_context(&input);
+        self.run(&mut context)?;
+        Ok(context.stages)
+    }
+
+    /// Applies the program, recording the word after every rule that changed it.
+    pub fn apply_traced(
+        &self,
+        input: Word,
+    ) -> std::result::Result<(Word, Vec<DerivationStep>), ApplicationError> {
+        let mut context: ExecutionContext = create_execution_context(&input);
+        context.trace = Some(Vec::new());
+        self.run(&mut context)?;
+        Ok((context.result, context.trace.unwrap_or_default()))
+    }
+
+    /// Proposes ancestor forms which the program turns into `target`, nearest first. Candidates
+    /// are found by editing `target` one letter at a time, keeping the edits whose output is
+    /// closest to `target`, so every word returned is checked by applying the program forward.
+    /// At most `max_candidates` words are returned, and the search stops after the program has
+    /// been run `REVERSE_MAX_APPLICATIONS` times.
+    pub fn apply_reverse(&self, target: &Word, max_candidates: usize) -> Vec<Word> {
+        if max_candidates == 0 {
+            return Vec::new();
+        }
+        let alphabet = self.reverse_alphabet();
+        let mut applications: usize = 0;
+
+        let mut seen: std::collections::HashSet<Vec<Letter>> = std::collections::HashSet::new();
+        let mut result: Vec<Word> = Vec::new();
+        let mut beam: Vec<(usize, Word)> = Vec::new();
+
+        seen.insert(target.letters.clone());
+        if let Some(distance) = self.reverse_distance(target, target) {
+            if distance == 0 {
+                result.push(target.clone());
+            }
+            beam.push((distance, target.clone()));
+        }
+
+        for _ in 0..REVERSE_MAX_EDITS {
+            let mut next: Vec<(usize, Word)> = Vec::new();
+            for (_, word) in &beam {
+                for candidate in single_edits(word, &alphabet) {
+                    if result.len() >= max_candidates || applications >= REVERSE_MAX_APPLICATIONS {
+                        return result;
+                    }
+                    if !seen.insert(candidate.letters.clone()) {
+                        continue;
+                    }
+                    applications += 1;
+                    if let Some(distance) = self.reverse_distance(&candidate, target) {
+                        if distance == 0 {
+                            result.push(candidate.clone());
+                        }
+                        next.push((distance, candidate));
+                    }
+                }
+            }
+            next.sort_by_key(|v| v.0);
+            next.truncate(REVERSE_BEAM_WIDTH);
+            beam = next;
+        }
+
+        result.truncate(max_candidates);
+        result
+    }
+
+    /// The letters `apply_reverse` edits words with: every letter some rule could change. A
+    /// letter no rule matches comes out the same as it went in, so an ancestor only has it
+    /// where the word it became does.
+    pub fn reverse_alphabet(&self) -> Vec<Letter> {
+        let mut predicates: Vec<&dyn Predicate> = Vec::new();
+        for rule in self.rules.iter().chain(self.subroutines.values().flatten()) {
+            if let Rule::TransformationRule { bytes, .. } = rule {
+                for byte in bytes
+                    .iter()
+                    .flat_map(|v| std::iter::once(v).chain(&v.variants))
+                {
+                    for t in &byte.transformations {
+                        predicates.extend(t.predicate.iter().map(|p| p.as_ref()));
+                    }
+                }
+            }
+        }
+
+        let mut alphabet: Vec<(&String, &Letter)> = self
+            .letter_to_symbol
+            .iter()
+            .filter(|(letter, _)| predicates.iter().any(|p| p.could_match(letter)))
+            .map(|(letter, symbol)| (symbol, letter))
+            .collect();
+        alphabet.sort_by(|a, b| a.0.cmp(b.0));
+        alphabet.into_iter().map(|(_, letter)| *letter).collect()
+    }
+
+    /// How many letter edits separate the program's output for `candidate` from `target`.
+    /// Candidates the program can't be applied to give `None` and are dropped from the search.
+    fn reverse_distance(&self, candidate: &Word, target: &Word) -> Option<usize> {
+        self.apply(candidate.clone())
+            .ok()
+            .map(|output| edit_distance(&output.letters, &target.letters))
+    }
+
+    fn run(&self, context: &mut ExecutionContext) -> std::result::Result<(), ApplicationError> {
+        self.auto_syllabify(&mut context.result);
         let mut instruction_count: u16 = 0;
         while context.instruction_ptr < self.rules.len() {
-            self.rules[context.instruction_ptr].apply(self, &mut context)?;
+            let rule = &self.rules[context.instruction_ptr];
+            rule.apply(self, context)?;
+            if matches!(
+                rule,
+                Rule::TransformationRule { .. } | Rule::CallSubroutine { .. }
+            ) {
+                self.apply_persistent_rules(context)?;
+            }
 
             if !context.jump_flag {
                 context.instruction_ptr += 1;
@@ -20,7 +169,99 @@ impl super::data::Program {
             }
         }
 
-        Ok(context.result)
+        Ok(())
+    }
+
+    /// Runs every persistent rule declared before the current instruction. The flags are
+    /// restored afterwards so jumps still see the result of the rule that was just run.
+    fn apply_persistent_rules(
+        &self,
+        context: &mut ExecutionContext,
+    ) -> std::result::Result<(), ApplicationError> {
+        let mod_flag = context.mod_flag;
+        let flag_flag = context.flag_flag;
+
+        for (start, name) in &self.persistent_rules {
+            if context.instruction_ptr < *start {
+                continue;
+            }
+            match self.subroutines.get(name) {
+                Some(rules) => {
+                    for rule in rules {
+                        rule.apply(self, context)?;
+                    }
+                }
+                None => {
+                    return Err(ApplicationError::InternalError(format!(
+                        "Subroutine not found: \"{}\"",
+                        name
+                    )))
+                }
+            }
+        }
+
+        context.mod_flag = mod_flag;
+        context.flag_flag = flag_flag;
+        Ok(())
+    }
+
+    /// Re-syllabifies the word if the program has an automatic syllables section.
+    fn auto_syllabify(&self, word: &mut Word) {
+        if let Some(structure) = &self.syllable_structure {
+            if structure.automatic {
+                structure.syllabify(word);
+            }
+        }
+    }
+
+    /// Turns a word in the program's spelling into phonemic symbols using the `deromanizer`
+    /// section. Without one the input is returned unchanged.
+    pub fn deromanize(&self, input: &str) -> String {
+        apply_spelling_rules(&self.deromanizer, input)
+    }
+
+    /// Turns phonemic symbols into the program's spelling using the `romanizer` section.
+    /// Without one the input is returned unchanged.
+    pub fn romanize(&self, input: &str) -> String {
+        apply_spelling_rules(&self.romanizer, input)
+    }
+
+    /// Writes out a word in the program's spelling. Syllable breaks, stress and tone aren't
+    /// spelled, so the `romanizer` only sees the letters and word breaks. Without a romanizer
+    /// the word is written with its symbols and all of its marks, the same as `to_string`.
+    pub fn romanize_word(&self, mut word: Word) -> std::result::Result<String, ApplicationError> {
+        if self.romanizer.is_empty() {
+            return to_string(self, word);
+        }
+        word.syllables.clear();
+        Ok(self.romanize(&to_string(self, word)?))
+    }
+
+    /// Reads a word written with the program's symbols, or in its spelling if `spelling` is
+    /// set. Spelling is never assumed, since the deromanizer could change symbols like `y`.
+    pub fn read_word(
+        &self,
+        input: &str,
+        spelling: bool,
+    ) -> std::result::Result<Word, ApplicationError> {
+        if spelling {
+            from_string(self, &self.deromanize(input))
+        } else {
+            from_string(self, &String::from(input))
+        }
+    }
+
+    /// Writes out a word with the program's symbols, or in its spelling if `spelling` is set.
+    pub fn write_word(
+        &self,
+        word: Word,
+        spelling: bool,
+    ) -> std::result::Result<String, ApplicationError> {
+        if spelling {
+            self.romanize_word(word)
+        } else {
+            to_string(self, word)
+        }
     }
 
     pub fn apply_vec(&self, input: Vec<Word>) -> std::result::Result<Vec<Word>, ApplicationError> {
@@ -43,6 +284,148 @@ impl super::data::Program {
     }
 }
 
+/// Runs the bytes of a transformation rule over the word, until it stops changing if the rule
+/// repeats. Returns the new word and whether any of the bytes modified it. A `first` or `last`
+/// rule changes only the first place any of its bytes match.
+fn apply_rule_bytes(
+    bytes: &[RuleByte],
+    input: Word,
+    flags: u16,
+    sites: Chance,
+) -> std::result::Result<(Word, bool), ApplicationError> {
+    let repeat = flags & RULE_FLAG_REPEAT != 0;
+    let once = flags & (RULE_FLAG_FIRST | RULE_FLAG_LAST) != 0;
+    let mut result = input;
+    let mut any_mod = false;
+    let mut iteration_count: u16 = 0;
+    loop {
+        let previous = if repeat { Some(result.clone()) } else { None };
+
+        if once {
+            //The rule as a whole applies once, so every line is tried at each position
+            let width = bytes.iter().map(|v| v.width()).min().unwrap_or(0);
+            scan_positions(&mut result, flags, sites, width, |word, i| {
+                for rule in bytes {
+                    if i + rule.width() > word.len() {
+                        continue;
+                    }
+                    if let Some(v) = rule.apply_at(word, i, &mut any_mod)? {
+                        return Ok(Some(v));
+                    }
+                }
+                Ok(None)
+            })?;
+        } else {
+            for rule in bytes {
+                result = rule.apply(result, &mut any_mod, flags, sites)?;
+            }
+        }
+
+        match previous {
+            Some(v) if v != result => {}
+            _ => break,
+        }
+
+        iteration_count += 1;
+        if iteration_count == u16::MAX {
+            return Err(ApplicationError::InternalError(String::from(
+                "Infinite loop detected; repeated rule u16::MAX times without settling",
+            )));
+        }
+    }
+    Ok((result, any_mod))
+}
+
+/// How many rounds of single letter edits `apply_reverse` makes before giving up.
+const REVERSE_MAX_EDITS: usize = 4;
+/// How many of the closest candidates `apply_reverse` keeps between rounds.
+const REVERSE_BEAM_WIDTH: usize = 24;
+/// How many candidates `apply_reverse` runs the program on before giving up, so a large
+/// inventory or a long word can't stall the caller.
+pub const REVERSE_MAX_APPLICATIONS: usize = 20000;
+
+/// Every word one substitution, insertion or deletion away from `word`.
+fn single_edits(word: &Word, alphabet: &[Letter]) -> Vec<Word> {
+    let mut result: Vec<Word> = Vec::new();
+    for i in 0..=word.len() {
+        if i < word.len() {
+            let mut removed = word.clone();
+            removed.remove(i);
+            result.push(removed);
+        }
+        for letter in alphabet {
+            if i < word.len() && word[i] != *letter {
+                let mut substituted = word.clone();
+                substituted[i] = *letter;
+                result.push(substituted);
+            }
+            let mut inserted = word.clone();
+            inserted.insert(i, *letter);
+            result.push(inserted);
+        }
+    }
+    result
+}
+
+/// The Levenshtein distance between two strings of letters.
+fn edit_distance(a: &[Letter], b: &[Letter]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut current: Vec<usize> = vec![i + 1];
+        for (j, y) in b.iter().enumerate() {
+            let cost = if x == y { 0 } else { 1 };
+            current.push(
+                (previous[j] + cost)
+                    .min(previous[j + 1] + 1)
+                    .min(current[j] + 1),
+            );
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Runs each spelling rule over the whole text in order. A rule replaces every match left to
+/// right, checking its enviorment against the text from before the rule ran.
+fn apply_spelling_rules(rules: &[SpellingRule], input: &str) -> String {
+    let mut current = String::from(input);
+    for rule in rules {
+        let mut result = String::with_capacity(current.len());
+        let mut i: usize = 0;
+        while i < current.len() {
+            if rule.matches_at(&current, i) {
+                result.push_str(&rule.to);
+                i += rule.from.len();
+            } else {
+                let c = current[i..].chars().next().unwrap();
+                result.push(c);
+                i += c.len_utf8();
+            }
+        }
+        current = result;
+    }
+    current
+}
+
+impl super::data::SpellingRule {
+    fn matches_at(&self, text: &str, i: usize) -> bool {
+        let (head, tail) = text.split_at(i);
+        if !tail.starts_with(&self.from) || !head.ends_with(&self.before) {
+            return false;
+        }
+        let tail = &tail[self.from.len()..];
+        if !tail.starts_with(&self.after) {
+            return false;
+        }
+
+        //Whitespace separates the words of a phrase
+        let head = &head[..head.len() - self.before.len()];
+        let tail = &tail[self.after.len()..];
+        (!self.before_boundary || head.chars().last().is_none_or(char::is_whitespace))
+            && (!self.after_boundary || tail.chars().next().is_none_or(char::is_whitespace))
+    }
+}
+
 impl super::data::Rule {
     pub fn apply(
         &self,
@@ -52,21 +435,67 @@ impl super::data::Rule {
         match self {
             Rule::TransformationRule {
                 bytes,
-                flags: _,
-                name: _,
+                flags,
+                name,
+                percent,
+                line_number,
             } => {
                 context.flag_flag = false;
                 context.mod_flag = false;
 
-                for rule in bytes {
-                    let mut mod_flag: bool = false;
-                    context.result = rule.apply(
-                        std::mem::replace(&mut context.result, create_empty_word()),
-                        &mut mod_flag,
-                    )?;
+                if context.exceptions.contains(name) {
+                    return Ok(());
+                }
+                let before = context.trace.as_ref().map(|_| context.result.clone());
+
+                //Chances are worked out from each word alone, so its neighbours don't matter
+                let apply_word =
+                    |word: Word| -> std::result::Result<(Word, bool), ApplicationError> {
+                        let chance = match flags & (RULE_FLAG_DIFFUSE | RULE_FLAG_SPORADIC) {
+                            0 => create_certain_chance(),
+                            _ => create_chance(program, &word, name, *percent)?,
+                        };
+                        if flags & RULE_FLAG_DIFFUSE != 0 && !chance.allows(None) {
+                            return Ok((word, false));
+                        }
+                        let sites = if flags & RULE_FLAG_SPORADIC != 0 {
+                            chance
+                        } else {
+                            create_certain_chance()
+                        };
+                        apply_rule_bytes(bytes, word, *flags, sites)
+                    };
+
+                //Replaces with an empty struct to avoid ownership issues. I think this is faster than clone.
+                //Maybe use Option?
+                let input = std::mem::replace(&mut context.result, create_empty_word());
+                if flags & RULE_FLAG_SANDHI != 0 || input.word_breaks.is_empty() {
+                    let (result, mod_flag) = apply_word(input)?;
+                    context.result = result;
                     context.mod_flag = mod_flag;
-                    //Replaces with an empty struct to avoid ownership issues. I think this is faster than clone.
-                    //Maybe use Option?
+                } else {
+                    //Without sandhi each word of a phrase is changed on its own
+                    let mut words: Vec<Word> = Vec::new();
+                    for word in input.split_words() {
+                        let (result, mod_flag) = apply_word(word)?;
+                        context.mod_flag |= mod_flag;
+                        words.push(result);
+                    }
+                    context.result = join_words(words);
+                }
+                program.auto_syllabify(&mut context.result);
+
+                if let Some(before) = before {
+                    if before != context.result {
+                        let result = to_string(program, context.result.clone())?;
+                        if let Some(trace) = &mut context.trace {
+                            trace.push(DerivationStep {
+                                rule: name.clone(),
+                                line_number: *line_number,
+                                result,
+                            });
+                        }
+                    }
                 }
                 Ok(())
             }
@@ -145,8 +574,160 @@ impl super::data::Rule {
                 context.flag_flag = false;
                 Ok(())
             }
+            Rule::Stage { name } => {
+                context.stages.push((name.clone(), context.result.clone()));
+                Ok(())
+            }
+            Rule::Syllabify => match &program.syllable_structure {
+                Some(structure) => {
+                    structure.syllabify(&mut context.result);
+                    Ok(())
+                }
+                None => Err(ApplicationError::InternalError(String::from(
+                    "Cannot syllabify without a syllables section",
+                ))),
+            },
+        }
+    }
+}
+
+impl super::data::SyllableStructure {
+    /// Replaces the syllables of the word. Nuclei are found left to right, taking the longest
+    /// match at each position. The consonants between two nuclei are split so the following
+    /// onset is as long as possible while both margins still match a declared pattern.
+    pub fn syllabify(&self, word: &mut Word) {
+        if !word.word_breaks.is_empty() {
+            let mut words = word.split_words();
+            for x in &mut words {
+                self.syllabify(x);
+            }
+            *word = join_words(words);
+            return;
+        }
+
+        let mut nuclei: Vec<(usize, usize)> = Vec::new();
+        let mut i: usize = 0;
+        while i < word.len() {
+            match self.longest_nucleus(word, i, word.len()) {
+                Some(end) => {
+                    nuclei.push((i, end));
+                    i = end;
+                }
+                None => i += 1,
+            }
+        }
+
+        let mut starts: Vec<usize> = vec![0];
+        for pair in nuclei.windows(2) {
+            starts.push(self.split_cluster(word, pair[0].1, pair[1].0));
+        }
+
+        let mut syllables: Vec<SyllableDefinition> = Vec::with_capacity(starts.len());
+        for (j, start) in starts.iter().enumerate() {
+            let end = starts.get(j + 1).copied().unwrap_or(word.len());
+            //Stress and tone stay with the nucleus they were on
+            let previous = word.syllable_at(nuclei.get(j).map_or(*start, |n| n.0));
+            syllables.push(SyllableDefinition {
+                start: *start,
+                end,
+                stress: previous.stress,
+                tone: previous.tone,
+            });
+        }
+        word.syllables = syllables;
+    }
+
+    /// Returns the span of the first nucleus inside the given syllable, if it has one.
+    pub fn find_nucleus(
+        &self,
+        word: &Word,
+        syllable: SyllableDefinition,
+    ) -> Option<(usize, usize)> {
+        let limit = syllable.end.min(word.len());
+        (syllable.start..limit)
+            .find_map(|i| self.longest_nucleus(word, i, limit).map(|end| (i, end)))
+    }
+
+    fn longest_nucleus(&self, word: &Word, start: usize, limit: usize) -> Option<usize> {
+        self.nuclei
+            .iter()
+            .filter_map(|pattern| match_pattern(pattern, word, start, limit).into_iter().max())
+            .max()
+            .filter(|end| *end > start)
+    }
+
+    /// Finds where the syllable boundary goes in the cluster between two nuclei. If no split
+    /// satisfies both margins, the longest valid onset wins, and failing that the whole cluster
+    /// becomes a coda.
+    fn split_cluster(&self, word: &Word, start: usize, end: usize) -> usize {
+        let mut fallback: Option<usize> = None;
+        for split in start..=end {
+            if !margin_matches(&self.onsets, word, split, end) {
+                continue;
+            }
+            if margin_matches(&self.codas, word, start, split) {
+                return split;
+            }
+            fallback.get_or_insert(split);
+        }
+        fallback.unwrap_or(end)
+    }
+}
+
+fn margin_matches(
+    patterns: &[Vec<EnviormentPredicate>],
+    word: &Word,
+    start: usize,
+    end: usize,
+) -> bool {
+    patterns.is_empty()
+        || patterns
+            .iter()
+            .any(|pattern| match_pattern(pattern, word, start, end).contains(&end))
+}
+
+/// Returns every position a pattern starting at `start` could end at without passing `limit`.
+fn match_pattern(
+    pattern: &[EnviormentPredicate],
+    word: &Word,
+    start: usize,
+    limit: usize,
+) -> Vec<usize> {
+    if pattern.is_empty() {
+        return vec![start];
+    }
+
+    if pattern[0].max_quant == 0 {
+        if pattern[0].predicate.validate(word, start) {
+            return match_pattern(&pattern[1..], word, start, limit);
+        }
+        return Vec::new();
+    }
+
+    let mut result: Vec<usize> = Vec::new();
+    let mut count: u8 = 0;
+    let mut position = start;
+    loop {
+        if count >= pattern[0].min_quant {
+            result.append(&mut match_pattern(&pattern[1..], word, position, limit));
         }
+        if count >= pattern[0].max_quant
+            || position >= limit
+            || !pattern[0].predicate.validate(word, position)
+        {
+            break;
+        }
+        count += 1;
+        position += 1;
     }
+    result
+}
+
+/// What a rule byte matched: the predicate alternative used by each slot, and the captured values.
+struct RuleMatch {
+    alternatives: Vec<usize>,
+    captures: Vec<Option<LetterValue>>,
+    masks: Vec<LetterValue>,
 }
 
 impl super::data::RuleByte {
@@ -154,261 +735,485 @@ impl super::data::RuleByte {
         &self,
         input: Word,
         mod_flag: &mut bool,
+        flags: u16,
+        sites: Chance,
     ) -> std::result::Result<Word, ApplicationError> {
-        match self.transformations.len() == 1 {
-            true => Ok(self.apply_single(input, mod_flag)?),
-            false => Ok(self.apply_multi(input, mod_flag)?),
+        let mut result = input;
+        scan_positions(&mut result, flags, sites, self.width(), |word, i| {
+            self.apply_at(word, i, mod_flag)
+        })?;
+        Ok(result)
+    }
+
+    /// How many letters a match covers; 0 for insertions.
+    fn width(&self) -> usize {
+        if self.rewrite.is_empty()
+            && self.transformations.len() == 1
+            && self.transformations[0].predicate.is_empty()
+        {
+            0
+        } else {
+            self.transformations.len()
         }
     }
 
-    fn apply_single(
+    /// Applies the rule byte, or the first of its variants that matches, at position `i`.
+    /// Returns how much the word grew or shrank by, or `None` if none of them matched.
+    fn apply_at(
         &self,
-        input: Word,
+        word: &mut Word,
+        i: usize,
         mod_flag: &mut bool,
-    ) -> std::result::Result<Word, ApplicationError> {
-        if self.transformations[0].predicate.is_empty() {
-            Ok(self.apply_empty_predicate(input, mod_flag)?)
+    ) -> std::result::Result<Option<i32>, ApplicationError> {
+        for byte in std::iter::once(self).chain(&self.variants) {
+            if let Some(v) = byte.apply_variant_at(word, i, mod_flag)? {
+                return Ok(Some(v));
+            }
+        }
+        Ok(None)
+    }
+
+    fn apply_variant_at(
+        &self,
+        word: &mut Word,
+        i: usize,
+        mod_flag: &mut bool,
+    ) -> std::result::Result<Option<i32>, ApplicationError> {
+        if !self.rewrite.is_empty() {
+            self.apply_rewrite_at(word, i, mod_flag)
+        } else if self.transformations.len() > 1 {
+            self.apply_multi_at(word, i, mod_flag)
+        } else if self.transformations[0].predicate.is_empty() {
+            self.apply_empty_predicate_at(word, i, mod_flag)
         } else {
-            Ok(self.apply_single_simple(input, mod_flag)?)
+            self.apply_single_simple_at(word, i, mod_flag)
         }
     }
 
-    fn apply_empty_predicate(
+    fn apply_empty_predicate_at(
         &self,
-        input: Word,
+        result: &mut Word,
+        i: usize,
         mod_flag: &mut bool,
-    ) -> std::result::Result<Word, ApplicationError> {
-        let mut result = input;
-        let mut i: usize = 0;
+    ) -> std::result::Result<Option<i32>, ApplicationError> {
+        //i is the gap before letter i, so 0 is before the very first character
+        let flag = match i {
+            0 => self.enviorment.check_enviorment_for_initial(result),
+            _ => self.enviorment.check_enviorment(result, i, 0),
+        };
+        if !flag {
+            return Ok(None);
+        }
 
-        {
-            //Insert as very first character? Code duplication which is unideal
-            let flag = self.enviorment.check_enviorment_for_initial(&result);
-            if flag {
-                let rule = self.transformations[0].result[0].as_ref();
-                let temp = match rule.transform(&Letter { value: 0 }) {
-                    //Dummy input; there are better ways to do this
-                    Some(v) => v,
-                    None => {
-                        return Err(ApplicationError::InternalError(String::from(
-                            "Rule returned None",
-                        )))
-                    }
-                };
-                result.insert(0, temp);
-                *mod_flag = true;
-                i += 1;
+        let rule = self.transformations[0].result[0].as_ref();
+        let temp = match rule.transform(&Letter { value: 0 }) {
+            //Dummy input; there are better ways to do this
+            Some(v) => v,
+            None => {
+                return Err(ApplicationError::InternalError(String::from(
+                    "Rule returned None",
+                )))
             }
+        };
+        //A letter inserted at a word break ends the previous word, unless the enviorment puts
+        //a boundary right before it and not after it
+        let env = &self.enviorment;
+        let starts_word = (env.ante.is_empty() && env.ante_word_boundary)
+            || env.ante.first().is_some_and(|p| p.max_quant == 0);
+        let ends_word = (env.post.is_empty() && env.post_word_boundary)
+            || env.post.first().is_some_and(|p| p.max_quant == 0);
+        if result.word_breaks.contains(&i) && (!starts_word || ends_word) {
+            result.insert_after(i - 1, temp);
+        } else {
+            result.insert(i, temp);
         }
+        transform_syllable_at(result, i, rule);
+        *mod_flag = true;
 
-        while i < result.len() {
-            let flag = self.enviorment.check_enviorment(&result, i + 1, 0);
-            if flag {
-                let rule = self.transformations[0].result[0].as_ref();
-                let temp = match rule.transform(&Letter { value: 0 }) {
-                    //Dummy input; there are better ways to do this
-                    Some(v) => v,
-                    None => {
-                        return Err(ApplicationError::InternalError(String::from(
-                            "Rule returned None",
-                        )))
-                    }
-                };
-                result.insert(i + 1, temp);
-                *mod_flag = true;
-                i += 1;
+        Ok(Some(1))
+    }
+
+    fn apply_single_simple_at(
+        &self,
+        result: &mut Word,
+        i: usize,
+        mod_flag: &mut bool,
+    ) -> std::result::Result<Option<i32>, ApplicationError> {
+        let mut flag = false;
+
+        let mut captures: Vec<LetterValue> = vec![0; self.num_captures];
+        let mut masks: Vec<LetterValue> = vec![0; self.num_captures];
+
+        let mut j: usize = 0;
+        while j < self.transformations[0].predicate.len() {
+            let p = &self.transformations[0].predicate[j];
+            let temp = p.as_ref();
+            if temp.validate(result, i) {
+                flag = true;
+                let mut k: usize = 0;
+                while k < self.transformations[0].predicate_captures.len() {
+                    let x = self.transformations[0].predicate_captures[k].0;
+                    let m = self.transformations[0].predicate_captures[k].1;
+                    captures[x] = result[i].value & m;
+                    masks[x] = m;
+
+                    k += 1;
+                }
+                break;
             }
+            j += 1;
+        }
 
-            i += 1;
+        if !flag || !self.enviorment.check_enviorment(result, i, 1) {
+            return Ok(None);
         }
 
-        Ok(result)
+        let rule = match self.transformations[0].result.len() {
+            1 => self.transformations[0].result[0].as_ref(),
+            _ => self.transformations[0].result[j].as_ref(),
+        };
+        let temp = rule.transform(&result[i]);
+        *mod_flag = true;
+        match temp {
+            Some(mut val) => {
+                for x in &self.transformations[0].result_captures {
+                    val.value = (val.value & !masks[*x]) | captures[*x];
+                }
+                result[i] = val;
+                transform_syllable_at(result, i, rule);
+                Ok(Some(0))
+            }
+            None => {
+                result.remove(i);
+                Ok(Some(-1))
+            }
+        }
     }
 
-    fn apply_single_simple(
+    fn apply_rewrite_at(
         &self,
-        input: Word,
+        result: &mut Word,
+        i: usize,
         mod_flag: &mut bool,
-    ) -> std::result::Result<Word, ApplicationError> {
-        let mut result = input;
-        let mut i: usize = 0;
-        while i < result.len() {
-            let mut flag = false;
-
-            let mut captures: Vec<u64> = vec![0; self.num_captures];
-            let mut masks: Vec<u64> = vec![0; self.num_captures];
+    ) -> std::result::Result<Option<i32>, ApplicationError> {
+        let num = self.transformations.len();
+        let found = match self.match_at(result, i) {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let matched: Vec<Letter> = result.letters[i..i + num].to_vec();
 
-            let mut j: usize = 0;
-            while j < self.transformations[0].predicate.len() {
-                let p = &self.transformations[0].predicate[j];
-                let temp = p.as_ref();
-                if temp.validate(&result, i) {
-                    flag = true;
-                    let mut k: usize = 0;
-                    while k < self.transformations[0].predicate_captures.len() {
-                        let x = self.transformations[0].predicate_captures[k].0;
-                        let m = self.transformations[0].predicate_captures[k].1;
-                        captures[x] = result[i].value & m;
-                        masks[x] = m;
-
-                        k += 1;
+        //Results past the end of the predicate transform the last matched letter
+        let mut letters: Vec<(Letter, &dyn Result)> = Vec::new();
+        for (k, (results, result_captures)) in self.rewrite.iter().enumerate() {
+            let slot = k.min(num - 1);
+            let rule = match results.len() {
+                1 => results[0].as_ref(),
+                _ => results[found.alternatives[slot]].as_ref(),
+            };
+            let source = match rule.source() {
+                Some(position) => matched[position],
+                None => matched[slot],
+            };
+            if let Some(mut val) = rule.transform(&source) {
+                for x in result_captures {
+                    match found.captures[*x] {
+                        Some(v) => val.value = (val.value & !found.masks[*x]) | v,
+                        None => {
+                            return Err(ApplicationError::InternalError(format!(
+                                "Did receive captured value for capture id \"{}\"",
+                                x
+                            )))
+                        }
                     }
-                    break;
                 }
-                j += 1;
+                letters.push((val, rule));
             }
+        }
 
-            let mut i_adjustment: i32 = 0;
+        //Shared slots are overwritten in place so the syllables around them stay put
+        for (k, (letter, rule)) in letters.iter().enumerate() {
+            if k < num {
+                result[i + k] = *letter;
+            } else {
+                result.insert_after(i + k - 1, *letter);
+            }
+            transform_syllable_at(result, i + k, *rule);
+        }
+        for _ in letters.len()..num {
+            result.remove(i + letters.len());
+        }
+        *mod_flag = true;
 
-            if flag
-                && self
-                    .enviorment
-                    .check_enviorment(&result, (i as i32 + i_adjustment) as usize, 1)
-            {
-                let rule = match self.transformations[0].result.len() {
-                    1 => self.transformations[0].result[0].as_ref(),
-                    _ => self.transformations[0].result[j].as_ref(),
-                };
-                let temp = rule.transform(&result[(i as i32 + i_adjustment) as usize]);
-                match temp {
-                    Some(mut val) => {
-                        for x in &self.transformations[0].result_captures {
-                            val.value = (val.value & !masks[*x]) | captures[*x];
+        Ok(Some(letters.len() as i32 - num as i32))
+    }
+
+    /// Checks every slot of the rule byte and its enviorment at position `i`.
+    fn match_at(&self, result: &Word, i: usize) -> Option<RuleMatch> {
+        let num = self.transformations.len();
+
+        let mut idx: Vec<usize> = Vec::new();
+        let mut captures: Vec<Option<LetterValue>> = vec![None; self.num_captures];
+        let mut masks: Vec<LetterValue> = vec![0; self.num_captures];
+
+        let mut j: usize = 0;
+        let mut flag2 = true;
+        while j < self.transformations.len() {
+            let mut k: usize = 0;
+            let mut flag = false;
+            while k < self.transformations[j].predicate.len() {
+                let p = &self.transformations[j].predicate[k];
+                let temp = p.as_ref();
+                if temp.validate(result, i + j) {
+                    flag = true;
+                    let mut l: usize = 0;
+                    while l < self.transformations[j].predicate_captures.len() {
+                        let x = self.transformations[j].predicate_captures[l].0;
+                        let m = self.transformations[j].predicate_captures[l].1;
+                        match captures[x] {
+                            Some(v) => {
+                                //If this capture ID has already been used, now it needs to detect sameness
+                                if result[i + j].value & m != v {
+                                    flag = false; //Sike this actually isn't ok
+                                }
+                                //It matches; move on
+                            }
+                            None => {
+                                //New capture
+                                captures[x] = Some(result[i + j].value & m);
+                                masks[x] = m;
+                            }
                         }
-                        result[i] = val;
-                        *mod_flag = true;
-                    }
-                    None => {
-                        result.remove((i as i32 + i_adjustment) as usize);
-                        i_adjustment -= 1;
-                        *mod_flag = true;
+
+                        l += 1;
                     }
+                    idx.push(k);
+                    break;
                 }
+                k += 1;
             }
+            if !flag {
+                flag2 = false;
+            }
+            j += 1;
+        }
 
-            i += 1;
-            i = (i as i32 + i_adjustment) as usize;
+        if !flag2 || !self.enviorment.check_enviorment(result, i, num) {
+            return None;
         }
-        Ok(result)
+
+        Some(RuleMatch {
+            alternatives: idx,
+            captures,
+            masks,
+        })
     }
 
-    fn apply_multi(
+    fn apply_multi_at(
         &self,
-        input: Word,
+        result: &mut Word,
+        i: usize,
         mod_flag: &mut bool,
-    ) -> std::result::Result<Word, ApplicationError> {
+    ) -> std::result::Result<Option<i32>, ApplicationError> {
         let num = self.transformations.len();
+        let RuleMatch {
+            alternatives: idx,
+            captures,
+            masks,
+        } = match self.match_at(result, i) {
+            Some(v) => v,
+            None => return Ok(None),
+        };
 
-        let mut result = input;
-        if num > result.len() {
-            return Ok(result);
-        }
-        let mut i = 0;
-
-        while i <= result.len() - num {
-            let mut idx: Vec<usize> = Vec::new();
-            let mut captures: Vec<Option<u64>> = vec![None; self.num_captures];
-            let mut masks: Vec<u64> = vec![0; self.num_captures];
+        //Kept so results can refer to the original letters after earlier slots are rewritten
+        let matched: Vec<Letter> = result.letters[i..i + num].to_vec();
 
-            let mut j: usize = 0;
-            let mut flag2 = true;
-            while j < self.transformations.len() {
-                let mut k: usize = 0;
-                let mut flag = false;
-                while k < self.transformations[j].predicate.len() {
-                    let p = &self.transformations[j].predicate[k];
-                    let temp = p.as_ref();
-                    if temp.validate(&result, i + j) {
-                        flag = true;
-                        let mut l: usize = 0;
-                        while l < self.transformations[j].predicate_captures.len() {
-                            let x = self.transformations[j].predicate_captures[l].0;
-                            let m = self.transformations[j].predicate_captures[l].1;
-                            match captures[x] {
-                                Some(v) => {
-                                    //If this capture ID has already been used, now it needs to detect sameness
-                                    if result[i + j].value & m != v {
-                                        flag = false; //Sike this actually isn't ok
-                                    }
-                                    //It matches; move on
-                                }
-                                None => {
-                                    //New capture
-                                    captures[x] = Some(result[i + j].value & m);
-                                    masks[x] = m;
-                                }
+        let mut i_adjustment: i32 = 0;
+        let mut k: usize = 0;
+        while k < self.transformations.len() {
+            let rule = match self.transformations[k].result.len() {
+                1 => self.transformations[k].result[0].as_ref(),
+                _ => self.transformations[k].result[idx[k]].as_ref(),
+            };
+            let source = match rule.source() {
+                Some(position) => matched[position],
+                None => result[((i + k) as i32 + i_adjustment) as usize],
+            };
+            let temp = rule.transform(&source);
+            match temp {
+                Some(mut val) => {
+                    for x in &self.transformations[k].result_captures {
+                        match captures[*x] {
+                            Some(v) => val.value = (val.value & !masks[*x]) | v,
+                            None => {
+                                return Err(ApplicationError::InternalError(format!(
+                                    "Did receive captured value for capture id \"{}\"",
+                                    x
+                                )))
                             }
-
-                            l += 1;
                         }
-                        idx.push(k);
-                        break;
                     }
-                    k += 1;
+                    result[((i + k) as i32 + i_adjustment) as usize] = val;
+                    transform_syllable_at(result, ((i + k) as i32 + i_adjustment) as usize, rule);
+                    *mod_flag = true;
                 }
-                if !flag {
-                    flag2 = false;
+                None => {
+                    result.remove(((i + k) as i32 + i_adjustment) as usize);
+                    *mod_flag = true;
+                    i_adjustment -= 1;
+                    if num > result.len() {
+                        return Ok(Some(i_adjustment));
+                    }
                 }
-                j += 1;
             }
+            k += 1;
+        }
 
-            let mut i_adjustment: i32 = 0;
+        Ok(Some(i_adjustment))
+    }
+}
 
-            if flag2 && self.enviorment.check_enviorment(&result, i, num) {
-                let mut k: usize = 0;
-                while k < self.transformations.len() {
-                    let rule = match self.transformations[k].result.len() {
-                        1 => self.transformations[k].result[0].as_ref(),
-                        _ => self.transformations[k].result[idx[k]].as_ref(),
-                    };
-                    let temp = rule.transform(&result[((i + k) as i32 + i_adjustment) as usize]);
-                    match temp {
-                        Some(mut val) => {
-                            for x in &self.transformations[k].result_captures {
-                                match captures[*x] {
-                                    Some(v) => val.value = (val.value & !masks[*x]) | v,
-                                    None => {
-                                        return Err(ApplicationError::InternalError(format!(
-                                            "Did receive captured value for capture id \"{}\"",
-                                            x
-                                        )))
-                                    }
-                                }
-                            }
-                            result[((i + k) as i32 + i_adjustment) as usize] = val;
-                            *mod_flag = true;
-                        }
-                        None => {
-                            result.remove(((i + k) as i32 + i_adjustment) as usize);
-                            *mod_flag = true;
-                            if num > result.len() {
-                                return Ok(result);
-                            }
-                            i_adjustment -= 1;
-                        }
+/// Applies a result's syllable-level changes to the syllable holding the letter at `position`.
+fn transform_syllable_at(word: &mut Word, position: usize, result: &dyn Result) {
+    if let Some(syllable) = word
+        .syllables
+        .iter_mut()
+        .find(|x| x.start <= position && position < x.end)
+    {
+        result.transform_syllable(syllable);
+    }
+}
+
+/// Walks every position a rule byte could apply at, in the order given by the rule flags.
+/// `width` is how many letters a match covers, so the final position is `len - width`.
+/// `apply_at` returns how much the word grew or shrank by if it applied at that position.
+fn scan_positions<F>(
+    word: &mut Word,
+    flags: u16,
+    sites: Chance,
+    width: usize,
+    mut apply_at: F,
+) -> std::result::Result<(), ApplicationError>
+where
+    F: FnMut(&mut Word, usize) -> std::result::Result<Option<i32>, ApplicationError>,
+{
+    let once = flags & (RULE_FLAG_FIRST | RULE_FLAG_LAST) != 0;
+    //Last match only is the first match found when scanning the other way
+    let reverse = (flags & RULE_FLAG_RTL != 0) != (flags & RULE_FLAG_LAST != 0);
+
+    if word.len() < width {
+        return Ok(());
+    }
+
+    if reverse {
+        let mut i = word.len() - width;
+        loop {
+            if sites.allows(Some(i)) && apply_at(word, i)?.is_some() && once {
+                break;
+            }
+            if i == 0 || word.len() < width {
+                break;
+            }
+            //Changes only ever affect letters at or after i, so earlier positions are still valid
+            i = (i - 1).min(word.len() - width);
+        }
+    } else {
+        let mut i: usize = 0;
+        while i + width <= word.len() {
+            if !sites.allows(Some(i)) {
+                i += 1;
+                continue;
+            }
+            match apply_at(word, i)? {
+                Some(adjustment) => {
+                    if once {
+                        break;
                     }
-                    k += 1;
+                    i = (i as i32 + 1 + adjustment).max(0) as usize;
                 }
+                None => i += 1,
             }
+        }
+    }
+
+    Ok(())
+}
 
-            i += 1;
-            i = (i as i32 + i_adjustment) as usize;
+impl super::data::Chance {
+    /// Whether the rule applies to the word, or to the site at the given position.
+    pub fn allows(&self, site: Option<usize>) -> bool {
+        if self.percent >= 100 {
+            return true;
         }
-        Ok(result)
+        let mut hash = self.seed;
+        if let Some(i) = site {
+            for b in (i as u64).to_le_bytes() {
+                hash ^= b as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        //The low bits of FNV are poorly mixed, so use the high ones
+        (hash >> 32) % 100 < self.percent as u64
     }
 }
 
 impl super::data::Enviorment {
+    /// Checks the letters from `start_position` to `start_position + length` against the
+    /// enviorment, its alternatives and its exceptions.
     fn check_enviorment(&self, input: &Word, start_position: usize, length: usize) -> bool {
+        let matched = self.check_clause(input, start_position, length)
+            || self
+                .alternatives
+                .iter()
+                .any(|e| e.check_clause(input, start_position, length));
+        matched != self.inverted
+            && !self
+                .exceptions
+                .iter()
+                .any(|e| e.check_clause(input, start_position, length))
+    }
+
+    fn check_enviorment_for_initial(&self, input: &Word) -> bool {
+        let matched = self.check_clause_for_initial(input)
+            || self
+                .alternatives
+                .iter()
+                .any(|e| e.check_clause_for_initial(input));
+        matched != self.inverted
+            && !self
+                .exceptions
+                .iter()
+                .any(|e| e.check_clause_for_initial(input))
+    }
+
+    fn check_clause(&self, input: &Word, start_position: usize, length: usize) -> bool {
         let mut j: usize = 0;
         let mut position_ante = start_position;
         let mut position_post = start_position + length;
         let mut accum: u8 = 0;
 
         while j < self.ante.len() {
+            if let Some(transparent) = &self.ante[j].transparent {
+                let positions = (0..position_ante).rev();
+                match find_past_transparent(&self.ante[j], transparent.as_ref(), input, positions) {
+                    Some(v) => position_ante = v,
+                    None => return false,
+                }
+                j += 1;
+                continue;
+            }
+            if self.ante[j].max_quant == 0 {
+                if !self.ante[j].predicate.validate(input, position_ante) {
+                    return false;
+                }
+                j += 1;
+                continue;
+            }
             if position_ante == 0 {
-                if accum < self.ante[j].min_quant || j < self.ante.len() - 1 {
-                    return self.inverted;
+                if accum < self.ante[j].min_quant
+                    || !zero_width_matches(&self.ante[j + 1..], input, 0)
+                {
+                    return false;
                 }
                 break;
             }
@@ -422,7 +1227,7 @@ impl super::data::Enviorment {
                     accum = 0;
                 }
             } else if accum < self.ante[j].min_quant {
-                return self.inverted;
+                return false;
             } else {
                 j += 1;
                 accum = 0;
@@ -431,16 +1236,48 @@ impl super::data::Enviorment {
                 }
             }
         }
-        if self.ante_word_boundary && position_ante != 0 {
-            return self.inverted;
+        if self.ante_word_boundary && !input.is_word_start(position_ante) {
+            return false;
+        }
+        if self.ante_phrase_boundary && !input.is_phrase_start(position_ante) {
+            return false;
         }
 
         j = 0;
         let mut flag = true;
         while j < self.post.len() {
+            if let Some(transparent) = &self.post[j].transparent {
+                let next = if flag {
+                    position_post
+                } else {
+                    position_post + 1
+                };
+                let positions = next..input.len();
+                match find_past_transparent(&self.post[j], transparent.as_ref(), input, positions) {
+                    Some(v) => position_post = v,
+                    None => return false,
+                }
+                flag = false;
+                j += 1;
+                continue;
+            }
+            if self.post[j].max_quant == 0 {
+                let gap = if flag {
+                    position_post
+                } else {
+                    position_post + 1
+                };
+                if !self.post[j].predicate.validate(input, gap) {
+                    return false;
+                }
+                j += 1;
+                continue;
+            }
             if (!flag && position_post >= input.len() - 1) || position_post == input.len() {
-                if accum < self.post[j].min_quant || j < self.post.len() - 1 {
-                    return self.inverted;
+                if accum < self.post[j].min_quant
+                    || !zero_width_matches(&self.post[j + 1..], input, input.len())
+                {
+                    return false;
                 }
                 break;
             }
@@ -458,7 +1295,7 @@ impl super::data::Enviorment {
                     accum = 0;
                 }
             } else if accum < self.post[j].min_quant {
-                return self.inverted;
+                return false;
             } else {
                 j += 1;
                 accum = 0;
@@ -467,22 +1304,26 @@ impl super::data::Enviorment {
                 }
             }
         }
-        if self.post_word_boundary {
-            if !self.post.is_empty() {
-                if position_post != input.len() - 1 {
-                    return self.inverted;
-                }
-            } else if position_post != input.len() {
-                return self.inverted;
-            }
+        let end = if flag {
+            position_post
+        } else {
+            position_post + 1
+        };
+        if self.post_word_boundary && !input.is_word_end(end) {
+            return false;
+        }
+        if self.post_phrase_boundary && !input.is_phrase_end(end) {
+            return false;
         }
 
-        !self.inverted
+        true
     }
 
-    fn check_enviorment_for_initial(&self, input: &Word) -> bool {
-        if !self.ante.is_empty() {
-            return self.inverted;
+    fn check_clause_for_initial(&self, input: &Word) -> bool {
+        if !zero_width_matches(&self.ante, input, 0)
+            || (self.ante_phrase_boundary && !input.is_phrase_start(0))
+        {
+            return false;
         }
 
         let mut position_post = 0;
@@ -491,9 +1332,38 @@ impl super::data::Enviorment {
         let mut j: usize = 0;
         let mut flag = true;
         while j < self.post.len() {
+            if let Some(transparent) = &self.post[j].transparent {
+                let next = if flag {
+                    position_post
+                } else {
+                    position_post + 1
+                };
+                let positions = next..input.len();
+                match find_past_transparent(&self.post[j], transparent.as_ref(), input, positions) {
+                    Some(v) => position_post = v,
+                    None => return false,
+                }
+                flag = false;
+                j += 1;
+                continue;
+            }
+            if self.post[j].max_quant == 0 {
+                let gap = if flag {
+                    position_post
+                } else {
+                    position_post + 1
+                };
+                if !self.post[j].predicate.validate(input, gap) {
+                    return false;
+                }
+                j += 1;
+                continue;
+            }
             if (!flag && position_post >= input.len() - 1) || position_post == input.len() {
-                if accum < self.post[j].min_quant || j < self.post.len() - 1 {
-                    return self.inverted;
+                if accum < self.post[j].min_quant
+                    || !zero_width_matches(&self.post[j + 1..], input, input.len())
+                {
+                    return false;
                 }
                 break;
             }
@@ -511,7 +1381,7 @@ impl super::data::Enviorment {
                     accum = 0;
                 }
             } else if accum < self.post[j].min_quant {
-                return self.inverted;
+                return false;
             } else {
                 j += 1;
                 accum = 0;
@@ -520,37 +1390,128 @@ impl super::data::Enviorment {
                 }
             }
         }
-        if self.post_word_boundary && position_post != input.len() - 1 {
-            return self.inverted;
+        let end = if flag {
+            position_post
+        } else {
+            position_post + 1
+        };
+        if self.post_word_boundary && !input.is_word_end(end) {
+            return false;
+        }
+        if self.post_phrase_boundary && !input.is_phrase_end(end) {
+            return false;
         }
 
-        !self.inverted
+        true
     }
 }
 
+/// Goes through `positions` in order for a letter matching `pattern`, skipping letters that
+/// match `transparent`. Gives `None` if any other letter, or the edge of the word, comes first.
+fn find_past_transparent(
+    pattern: &EnviormentPredicate,
+    transparent: &dyn Predicate,
+    input: &Word,
+    positions: impl Iterator<Item = usize>,
+) -> Option<usize> {
+    for i in positions {
+        if pattern.predicate.validate(input, i) {
+            return Some(i);
+        }
+        if !transparent.validate(input, i) {
+            return None;
+        }
+    }
+    None
+}
+
+/// Whether every remaining predicate takes up no letters and matches the given gap.
+fn zero_width_matches(wing: &[EnviormentPredicate], input: &Word, gap: usize) -> bool {
+    wing.iter()
+        .all(|p| p.max_quant == 0 && p.predicate.validate(input, gap))
+}
+
 pub fn from_string(
     program: &Program,
     input: &String,
 ) -> std::result::Result<Word, ApplicationError> {
-    let mut string = input.clone();
+    let mut string = String::from(input.trim());
     let mut result: Vec<Letter> = Vec::new();
     let mut syllables: Vec<SyllableDefinition> = Vec::new();
+    let mut word_breaks: Vec<usize> = Vec::new();
     let mut keys: Vec<&str> = Vec::new();
     for k in program.symbol_to_letter.keys() {
         keys.push(k);
     }
     keys.sort_unstable_by_key(|b| std::cmp::Reverse(b.chars().count()));
 
+    //Tone numbers are only read as tones when no symbol or diacritic could use them
+    let tone_level = |c: char| -> Option<u16> {
+        if let Some(i) = TONE_LETTERS.iter().position(|x| *x == c) {
+            return Some(i as u16 + 1);
+        }
+        if !('1'..='5').contains(&c)
+            || keys.iter().any(|k| k.starts_with(c))
+            || program
+                .diacritics
+                .iter()
+                .any(|d| d.diacritic.starts_with(c))
+        {
+            return None;
+        }
+        c.to_digit(10).map(|v| v as u16)
+    };
+
     let mut depth: usize = 0;
     let mut flag = false;
     let mut index: usize = 0;
+    //Set after a tone mark, which closes its syllable
+    let mut tone_closed = false;
     syllables.push(create_syllable_definition(0, 0)?);
     while !string.is_empty() {
+        //Spaces separate the words of a phrase, and also end the syllable
+        if string.starts_with(char::is_whitespace) {
+            string = String::from(string.trim_start());
+            let i = syllables.len() - 1;
+            if syllables[i].start != index {
+                syllables[i].end = index;
+                syllables.push(create_syllable_definition(index, index)?);
+            }
+            word_breaks.push(index);
+            tone_closed = false;
+            flag = false;
+        }
         if string.starts_with('.') {
             let i = syllables.len() - 1;
             syllables[i].end = index;
             syllables.push(create_syllable_definition(index, index)?);
-            string = String::from(string.strip_prefix('.').unwrap())
+            string = String::from(string.strip_prefix('.').unwrap());
+            tone_closed = false;
+        }
+        if string.starts_with('ˈ') || string.starts_with('ˌ') {
+            let i = syllables.len() - 1;
+            if syllables[i].start != index {
+                syllables[i].end = index;
+                syllables.push(create_syllable_definition(index, index)?);
+            }
+            let i = syllables.len() - 1;
+            syllables[i].stress = match string.remove(0) {
+                'ˈ' => Stress::Primary,
+                _ => Stress::Secondary,
+            };
+            tone_closed = false;
+        }
+        while let Some(level) = string.chars().next().and_then(tone_level) {
+            let i = syllables.len() - 1;
+            if syllables[i].tone >= 10u16.pow(MAX_TONE_LEVELS as u32 - 1) {
+                return Err(ApplicationError::IntoConversionError(format!(
+                    "Too many tone levels in \"{0}\"",
+                    input
+                )));
+            }
+            syllables[i].tone = syllables[i].tone * 10 + level;
+            string.remove(0);
+            tone_closed = true;
         }
         if flag {
             for d in &program.diacritics {
@@ -571,9 +1532,17 @@ pub fn from_string(
             if string.starts_with(k) {
                 string = String::from(string.strip_prefix(k).unwrap());
                 let (letter, _) = program.symbol_to_letter.get(*k).unwrap();
+                if tone_closed {
+                    let i = syllables.len() - 1;
+                    syllables[i].end = index;
+                    syllables.push(create_syllable_definition(index, index)?);
+                    tone_closed = false;
+                }
                 result.push(*letter);
                 index += 1;
                 flag = true;
+                //Start again from the longest symbol, so the next letter is also read greedily
+                break;
             }
         }
         depth += 1;
@@ -586,5 +1555,7 @@ pub fn from_string(
     }
     let i = syllables.len() - 1;
     syllables[i].end = index;
-    Ok(create_word_syllables(result, syllables))
+    let mut word = create_word_syllables(result, syllables);
+    word.word_breaks = word_breaks;
+    Ok(word)
 }
@@ -0,0 +1,134 @@
+use super::constructor::{construct_with_path, expanded_source};
+use super::data::*;
+use bincode::Options;
+use std::rc::Rc;
+
+/// Every cache file starts with this, then the format version, so anything else is never
+/// deserialized.
+const CACHE_MAGIC: &[u8] = b"LEXICA-SC-CACHE\0";
+/// Changed whenever `Program` or anything in it changes shape, so old caches are rebuilt.
+const CACHE_FORMAT_VERSION: u32 = 4;
+/// The most a cache may hold. Anyone can write to a cache file, so a length read from one is
+/// never trusted beyond this.
+const MAX_CACHE_SIZE: u64 = 64 << 20;
+
+/// Constructs the program loaded from `path`, or loads it from the cache next to it if
+/// neither it nor anything it includes has changed since the cache was written. The cache is
+/// written after constructing; if it can't be, the program is still returned.
+pub fn construct_cached(input: &str, path: &str) -> std::result::Result<Program, ConstructorError> {
+    use std::time::Instant;
+    let now = Instant::now();
+
+    if let Some(program) = load_cached(input, path) {
+        let elapsed = now.elapsed();
+        println!("Loaded cached program in {:.2?}", elapsed);
+        return Ok(program);
+    }
+
+    let program = construct_with_path(input, path)?;
+    let cache_path = get_cache_path(path);
+    if let Err(v) = save_cache(&cache_path, hash_source(input, path), &program) {
+        println!("Couldn't cache program at {}: {}", cache_path, v);
+    }
+    Ok(program)
+}
+
+/// Loads the cached program for `path` if it was built from the same source by a build with
+/// the same cache format. A missing, stale or unreadable cache all give `None`.
+pub fn load_cached(input: &str, path: &str) -> Option<Program> {
+    let bytes = std::fs::read(get_cache_path(path)).ok()?;
+    //The whole header is compared before anything is deserialized
+    let body = bytes.strip_prefix(&cache_header(hash_source(input, path))[..])?;
+    let mut program: Program = cache_options().deserialize(body).ok()?;
+    share_syllable_structure(&mut program);
+    Some(program)
+}
+
+/// The cache for `program.lsc` is `program.lsc.cache`.
+pub fn get_cache_path(path: &str) -> String {
+    format!("{}.cache", path)
+}
+
+/// The magic bytes, format version, crate version and source hash, in that order.
+fn cache_header(hash: u64) -> Vec<u8> {
+    let mut header = Vec::from(CACHE_MAGIC);
+    header.extend_from_slice(&CACHE_FORMAT_VERSION.to_le_bytes());
+    header.extend_from_slice(env!("CARGO_PKG_VERSION").as_bytes());
+    header.push(0);
+    header.extend_from_slice(&hash.to_le_bytes());
+    header
+}
+
+fn hash_source(input: &str, path: &str) -> u64 {
+    let source = expanded_source(input, path);
+    hash_fnv1a(path.bytes().chain([0]).chain(source.bytes()))
+}
+
+fn save_cache(cache_path: &str, hash: u64, program: &Program) -> std::result::Result<(), String> {
+    let mut bytes = cache_header(hash);
+    cache_options()
+        .serialize_into(&mut bytes, program)
+        .map_err(|v| v.to_string())?;
+    std::fs::write(cache_path, bytes).map_err(|v| v.to_string())
+}
+
+fn cache_options() -> impl Options {
+    bincode::options().with_limit(MAX_CACHE_SIZE)
+}
+
+/// Predicates that need the syllable structure share the program's one, but a loaded program
+/// has none in its predicates until they are pointed at it again.
+fn share_syllable_structure(program: &mut Program) {
+    let structure = match &program.syllable_structure {
+        Some(structure) => structure.clone(),
+        None => return,
+    };
+    let subroutines = program.subroutines.values_mut().flatten();
+    for rule in program.rules.iter_mut().chain(subroutines) {
+        match rule {
+            Rule::TransformationRule { bytes, .. } => {
+                for byte in bytes {
+                    share_in_rule_byte(byte, &structure);
+                }
+            }
+            Rule::Detect {
+                predicate,
+                enviorment,
+            } => {
+                for p in predicate {
+                    p.share_structure(&structure);
+                }
+                share_in_enviorment(enviorment, &structure);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn share_in_rule_byte(byte: &mut RuleByte, structure: &Rc<SyllableStructure>) {
+    for transformation in &mut byte.transformations {
+        for p in &mut transformation.predicate {
+            p.share_structure(structure);
+        }
+    }
+    share_in_enviorment(&mut byte.enviorment, structure);
+    for variant in &mut byte.variants {
+        share_in_rule_byte(variant, structure);
+    }
+}
+
+fn share_in_enviorment(enviorment: &mut Enviorment, structure: &Rc<SyllableStructure>) {
+    for p in enviorment.ante.iter_mut().chain(&mut enviorment.post) {
+        p.predicate.share_structure(structure);
+        if let Some(transparent) = &mut p.transparent {
+            transparent.share_structure(structure);
+        }
+    }
+    for other in enviorment
+        .alternatives
+        .iter_mut()
+        .chain(&mut enviorment.exceptions)
+    {
+        share_in_enviorment(other, structure);
+    }
+}
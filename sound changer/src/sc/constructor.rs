@@ -1,8 +1,17 @@
-use std::{collections::*, vec};
+use std::{
+    collections::*,
+    path::{Path, PathBuf},
+    rc::Rc,
+    vec,
+};
 
 use crate::fancy_regex::Regex;
+use crate::io::load_from_file;
 use {super::applicator::*, super::data::*, super::rules::*};
 
+/// Caps how many rule bytes feature variables can expand a single line into.
+const MAX_FEATURE_VARIABLE_EXPANSIONS: usize = 1024;
+
 macro_rules! error {
     ($name:expr, $error_type:expr) => {
         return Err(create_constructor_error_empty($name, line!(), $error_type))
@@ -34,233 +43,599 @@ enum State {
     Features,
     Symbols,
     Diacritics,
+    Syllables,
+    Classes,
+    Romanizer,
+    Deromanizer,
     Rules,
     RuleAccum(RuleBlockType),
 }
 
+/// Constructs a program. Included files are found relative to the working directory.
 pub fn construct(input: &str) -> std::result::Result<Program, ConstructorError> {
+    construct_with_path(input, "")
+}
+
+/// Constructs a program loaded from `path`, so included files are found relative to it.
+pub fn construct_with_path(
+    input: &str,
+    path: &str,
+) -> std::result::Result<Program, ConstructorError> {
+    construct_lines(input, Some(path), false).map_err(|mut v| v.remove(0))
+}
+
+/// Constructs a program like `construct_with_path`, but keeps going after an error by skipping
+/// the broken line or rule block. Returns every error found, in order.
+pub fn construct_recovering(
+    input: &str,
+    path: &str,
+) -> std::result::Result<Program, Vec<ConstructorError>> {
+    construct_lines(input, Some(path), true)
+}
+
+/// Constructs a program that wasn't loaded from a file, like one sent from the editor, keeping
+/// going after errors like `construct_recovering`. Includes are found relative to the file
+/// doing the including, so here they are errors.
+pub fn construct_detached(input: &str) -> std::result::Result<Program, Vec<ConstructorError>> {
+    construct_lines(input, None, true)
+}
+
+fn construct_lines(
+    input: &str,
+    path: Option<&str>,
+    recover: bool,
+) -> std::result::Result<Program, Vec<ConstructorError>> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut sources: Vec<(Option<String>, u32)> = Vec::new();
+    let mut include_errors: Vec<ConstructorError> = Vec::new();
+    expand_includes(
+        input,
+        path.map(Path::new),
+        None,
+        &mut Vec::new(),
+        &mut lines,
+        &mut sources,
+        &mut include_errors,
+    );
+    if !recover && !include_errors.is_empty() {
+        return Err(include_errors);
+    }
+
+    let mut errors: Vec<ConstructorError> = Vec::new();
+    let mut program = construct_expanded(&lines.join("\n"), &mut errors, recover);
+
+    //Errors and rule lines are found in the combined program, so point them back at the file
+    //they came from
+    if !include_errors.is_empty() || !errors.is_empty() {
+        for e in &mut errors {
+            if let LineNumberInformation::Raw(v) = e.line_number_user_program {
+                let index = (v as usize).wrapping_sub(1);
+                if e.column_span.is_none() && e.error_type != ConstructorErrorType::HangingSection {
+                    e.column_span = lines.get(index).map(|line| guess_column_span(e, line));
+                }
+                if let Some((file, line_number)) = sources.get(index) {
+                    e.line_number_user_program = LineNumberInformation::Raw(*line_number);
+                    e.file = file.clone();
+                }
+            }
+        }
+        include_errors.append(&mut errors);
+        return Err(include_errors);
+    }
+
+    let rules = program
+        .rules
+        .iter_mut()
+        .chain(program.subroutines.values_mut().flatten());
+    for rule in rules {
+        if let Rule::TransformationRule { line_number, .. } = rule {
+            if let Some((_, v)) = sources.get((*line_number as usize).wrapping_sub(1)) {
+                *line_number = *v;
+            }
+        }
+    }
+    Ok(program)
+}
+
+/// The program with every include written out, so a change to any file it is built from
+/// changes the result. Broken includes are left out.
+pub fn expanded_source(input: &str, path: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    expand_includes(
+        input,
+        Some(Path::new(path)),
+        None,
+        &mut Vec::new(),
+        &mut lines,
+        &mut Vec::new(),
+        &mut Vec::new(),
+    );
+    lines.join("\n")
+}
+
+/// Guesses which columns of `line` an error is about: the first name the message quotes or
+/// else its last word, if the line contains it, and otherwise the whole line without
+/// indentation or comments.
+fn guess_column_span(error: &ConstructorError, line: &str) -> (u32, u32) {
+    let code = line.split('#').next().unwrap().trim_end();
+    let indent = code.chars().take_while(|c| c.is_whitespace()).count();
+
+    let message = &error.error_message;
+    let name = match message.split('"').nth(1) {
+        Some(v) => v,
+        None => message.rsplit(' ').next().unwrap_or(""),
+    };
+    match code.find(name) {
+        Some(start) if !name.is_empty() => {
+            let start = code[..start].chars().count();
+            (start as u32, (start + name.chars().count()) as u32)
+        }
+        _ => (indent as u32, code.chars().count() as u32),
+    }
+}
+
+/// Replaces every `include "path.lsc"` line with the lines of that file, recording which file
+/// and line each resulting line came from. A broken include is skipped and added to `errors`.
+/// `path` is `None` for a program that isn't in a file, which can't include anything.
+fn expand_includes(
+    input: &str,
+    path: Option<&Path>,
+    file: Option<String>,
+    stack: &mut Vec<PathBuf>,
+    lines: &mut Vec<String>,
+    sources: &mut Vec<(Option<String>, u32)>,
+    errors: &mut Vec<ConstructorError>,
+) {
+    for (i, line) in input.split('\n').enumerate() {
+        let line_number = i as u32 + 1;
+        let trimmed = line.split('#').next().unwrap().trim();
+        let target = match trimmed.strip_prefix("include") {
+            Some(v) if v.is_empty() || v.starts_with(' ') => v.trim(),
+            _ => {
+                lines.push(String::from(line));
+                sources.push((file.clone(), line_number));
+                continue;
+            }
+        };
+
+        let mut include_error = |message: String, error_type: ConstructorErrorType| {
+            let mut error = create_constructor_error(
+                message,
+                String::from(line),
+                line_number,
+                line!(),
+                error_type,
+            );
+            error.file = file.clone();
+            error.column_span = Some(guess_column_span(&error, line));
+            errors.push(error);
+        };
+
+        if target.len() < 2 || !target.starts_with('"') || !target.ends_with('"') {
+            include_error(
+                String::from("Malformed include definition: Path must be in quotes"),
+                ConstructorErrorType::MalformedDefinition,
+            );
+            continue;
+        }
+        let path = match path {
+            Some(v) => v,
+            None => {
+                include_error(
+                    String::from("Malformed include definition: Only programs loaded from a file can include other files"),
+                    ConstructorErrorType::MalformedDefinition,
+                );
+                continue;
+            }
+        };
+        let included = path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(&target[1..target.len() - 1]);
+        let canonical = std::fs::canonicalize(&included).unwrap_or(included.clone());
+        if stack.contains(&canonical) {
+            include_error(
+                format!("File \"{}\" includes itself", included.display()),
+                ConstructorErrorType::MalformedDefinition,
+            );
+            continue;
+        }
+        let contents = match load_from_file(&included.display().to_string(), false) {
+            Ok(v) => v,
+            Err(v) => {
+                include_error(
+                    v.get_message().clone(),
+                    ConstructorErrorType::MissingInclude,
+                );
+                continue;
+            }
+        };
+
+        stack.push(canonical);
+        expand_includes(
+            &contents,
+            Some(&included),
+            Some(included.display().to_string()),
+            stack,
+            lines,
+            sources,
+            errors,
+        );
+        stack.pop();
+    }
+}
+
+/// The state carried from line to line while constructing a program.
+struct Construction<'a> {
+    state: State,
+    program: Program,
+    context: ProgramCreationContext,
+    rule_accum: Vec<&'a str>,
+    rule_accum_depth: u8,
+    /// Set when the rule block being accumulated already had an error, so it is skipped.
+    rule_accum_broken: bool,
+}
+
+/// Constructs the combined lines of a program. Errors are added to `errors`; with `recover`
+/// set, the broken line or rule block is skipped and construction carries on.
+fn construct_expanded(input: &str, errors: &mut Vec<ConstructorError>, recover: bool) -> Program {
     use std::time::Instant;
     let now = Instant::now();
 
-    let mut current_state = State::None;
-    let mut program = create_empty_program();
-    let mut context = create_program_creation_context();
-    let lines: Vec<&str> = input.split('\n').collect();
-
-    let mut rule_accum: Vec<&str> = Vec::new();
-    let mut rule_accum_depth: u8 = 0;
+    let mut construction = Construction {
+        state: State::None,
+        program: create_empty_program(),
+        context: create_program_creation_context(),
+        rule_accum: Vec::new(),
+        rule_accum_depth: 0,
+        rule_accum_broken: false,
+    };
 
     let mut line_number: u32 = 0;
     let regex: Regex = Regex::new(r" (?![^(]*\))(?![^\[]*\])").unwrap();
 
-    for f in lines {
+    for f in input.split('\n') {
         line_number += 1;
-
-        let line_og = f.clone();
-        let mut line = line_og.trim();
-
-        if line.contains('#') {
-            let temp: Vec<&str> = line.split('#').collect();
-            line = temp[0].trim();
+        if let Err(e) = construct_line(&mut construction, f, line_number, &regex) {
+            errors.push(e);
+            if !recover {
+                return construction.program;
+            }
         }
+    }
+    if let Err(e) = check_section_finished(construction.state, line_number) {
+        errors.push(e);
+    }
 
-        let mut temp = regex.replace_all(line, String::from_utf8(vec![0]).unwrap());
-        let words: Vec<&str> = temp.to_mut().split('\u{0000}').collect();
+    let elapsed = now.elapsed();
+    println!("Done loading and constructing program in {:.2?}", elapsed);
 
-        match current_state {
-            State::None => {
-                if words[0] == "feature_def" {
-                    current_state = State::Features;
-                } else if words[0] == "symbols" {
-                    current_state = State::Symbols;
-                } else if words[0] == "rules" {
-                    current_state = State::Rules;
-                } else if words[0] == "diacritics" {
-                    current_state = State::Diacritics;
-                } else if !words[0].is_empty() {
-                    error_detail!(
-                        format!("Unknown command \"{}\"", words[0]),
-                        ConstructorErrorType::UnknownCommandError,
-                        line_number,
-                        String::from(line_og)
-                    );
-                }
+    construction.program
+}
+
+fn construct_line<'a>(
+    c: &mut Construction<'a>,
+    line_og: &'a str,
+    line_number: u32,
+    regex: &Regex,
+) -> std::result::Result<(), ConstructorError> {
+    let mut line = line_og.trim();
+
+    if line.contains('#') {
+        let temp: Vec<&str> = line.split('#').collect();
+        line = temp[0].trim();
+    }
+
+    let mut temp = regex.replace_all(line, String::from_utf8(vec![0]).unwrap());
+    let words: Vec<&str> = temp.to_mut().split('\u{0000}').collect();
+
+    match c.state {
+        State::None => {
+            if words[0] == "feature_def" {
+                c.state = State::Features;
+            } else if words[0] == "symbols" {
+                c.state = State::Symbols;
+            } else if words[0] == "rules" {
+                c.state = State::Rules;
+            } else if words[0] == "diacritics" {
+                c.state = State::Diacritics;
+            } else if words[0] == "classes" {
+                c.state = State::Classes;
+            } else if words[0] == "romanizer" {
+                c.state = State::Romanizer;
+            } else if words[0] == "deromanizer" {
+                c.state = State::Deromanizer;
+            } else if words[0] == "syllables" {
+                c.state = State::Syllables;
+                handle_err(
+                    construct_syllable_structure(&mut c.program, &words),
+                    String::from(line_og),
+                    line_number,
+                )?;
+            } else if !words[0].is_empty() {
+                error_detail!(
+                    format!("Unknown command \"{}\"", words[0]),
+                    ConstructorErrorType::UnknownCommandError,
+                    line_number,
+                    String::from(line_og)
+                );
             }
-            State::Features => {
-                if words[0] == "switch" {
-                    handle_err(
-                        construct_switch_line(&mut program, &words),
-                        String::from(line_og),
-                        line_number,
-                    )?;
-                } else if words[0] == "feature" {
-                    handle_err(
-                        construct_feature_def(&mut program, &words),
-                        String::from(line_og),
-                        line_number,
-                    )?;
-                } else if words[0] == "end" {
-                    handle_err(
-                        end_feature_def(&mut program),
-                        String::from(line_og),
-                        line_number,
-                    )?;
-                    current_state = State::None;
-                } else if !words[0].is_empty() {
-                    error_detail!(
-                        format!("Unknown command \"{}\"", words[0]),
-                        ConstructorErrorType::UnknownCommandError,
-                        line_number,
-                        String::from(line_og)
-                    );
-                }
+        }
+        State::Features => {
+            if words[0] == "switch" {
+                handle_err(
+                    construct_switch_line(&mut c.program, &words),
+                    String::from(line_og),
+                    line_number,
+                )?;
+            } else if words[0] == "feature" {
+                handle_err(
+                    construct_feature_def(&mut c.program, &words),
+                    String::from(line_og),
+                    line_number,
+                )?;
+            } else if words[0] == "end" {
+                c.state = State::None;
+                handle_err(
+                    end_feature_def(&mut c.program),
+                    String::from(line_og),
+                    line_number,
+                )?;
+            } else if !words[0].is_empty() {
+                error_detail!(
+                    format!("Unknown command \"{}\"", words[0]),
+                    ConstructorErrorType::UnknownCommandError,
+                    line_number,
+                    String::from(line_og)
+                );
             }
-            State::Symbols => {
-                if words[0] == "symbol" {
-                    handle_err(
-                        construct_symbol(&mut program, &words),
-                        String::from(line_og),
-                        line_number,
-                    )?;
-                } else if words[0] == "end" {
-                    current_state = State::None;
-                } else if !words[0].is_empty() {
-                    error_detail!(
-                        format!("Unknown command \"{}\"", words[0]),
-                        ConstructorErrorType::UnknownCommandError,
-                        line_number,
-                        String::from(line_og)
-                    );
-                }
+        }
+        State::Symbols => {
+            if words[0] == "symbol" {
+                handle_err(
+                    construct_symbol(&mut c.program, &words),
+                    String::from(line_og),
+                    line_number,
+                )?;
+            } else if words[0] == "end" {
+                c.state = State::None;
+            } else if !words[0].is_empty() {
+                error_detail!(
+                    format!("Unknown command \"{}\"", words[0]),
+                    ConstructorErrorType::UnknownCommandError,
+                    line_number,
+                    String::from(line_og)
+                );
             }
-            State::Rules => {
-                if words[0] != "end" {
-                    context
-                        .rule_line_defs
-                        .insert(program.rules.len(), line_number);
-                }
-                if words[0] == "rule" {
-                    rule_accum.push(line);
-                    current_state = State::RuleAccum(RuleBlockType::Rule);
-                    rule_accum_depth = 1;
-                } else if words[0] == "subx" {
-                    rule_accum.push(line);
-                    current_state = State::RuleAccum(RuleBlockType::SubX);
-                    rule_accum_depth = 1;
-                } else if words[0] == "sub" {
-                    rule_accum.push(line);
-                    current_state = State::RuleAccum(RuleBlockType::Sub);
-                    rule_accum_depth = 1;
-                } else if words[0] == "call" {
-                    handle_err(
-                        construct_call(&mut program, &words),
-                        String::from(line_og),
-                        line_number,
-                    )?;
-                } else if words[0] == "detect" {
-                    handle_err(
-                        construct_detect(&mut program, &words),
-                        String::from(line_og),
-                        line_number,
-                    )?;
-                } else if words[0] == "label" {
-                    handle_err(
-                        construct_label(&mut program, &words),
-                        String::from(line_og),
-                        line_number,
-                    )?;
-                } else if words[0] == "jmp" {
-                    handle_err(
-                        construct_jump(&mut program, &words),
-                        String::from(line_og),
-                        line_number,
-                    )?;
-                } else if words[0] == "end" {
-                    check_jumps(&program, &context)?;
-                    current_state = State::None;
-                } else if !words[0].is_empty() {
+        }
+        State::Rules => {
+            if words[0] != "end" {
+                c.context
+                    .rule_line_defs
+                    .insert(c.program.rules.len(), line_number);
+            }
+            if words[0] == "rule" {
+                c.rule_accum.push(line);
+                c.state = State::RuleAccum(RuleBlockType::Rule);
+                c.rule_accum_depth = 1;
+            } else if words[0] == "subx" {
+                c.rule_accum.push(line);
+                c.state = State::RuleAccum(RuleBlockType::SubX);
+                c.rule_accum_depth = 1;
+            } else if words[0] == "sub" {
+                c.rule_accum.push(line);
+                c.state = State::RuleAccum(RuleBlockType::Sub);
+                c.rule_accum_depth = 1;
+            } else if words[0] == "call" {
+                handle_err(
+                    construct_call(&mut c.program, &words),
+                    String::from(line_og),
+                    line_number,
+                )?;
+            } else if words[0] == "detect" {
+                handle_err(
+                    construct_detect(&mut c.program, &words),
+                    String::from(line_og),
+                    line_number,
+                )?;
+            } else if words[0] == "label" {
+                handle_err(
+                    construct_label(&mut c.program, &words),
+                    String::from(line_og),
+                    line_number,
+                )?;
+            } else if words[0] == "jmp" {
+                handle_err(
+                    construct_jump(&mut c.program, &words),
+                    String::from(line_og),
+                    line_number,
+                )?;
+            } else if words[0] == "syllabify" {
+                handle_err(
+                    construct_syllabify(&mut c.program, &words),
+                    String::from(line_og),
+                    line_number,
+                )?;
+            } else if words[0] == "stage" {
+                handle_err(
+                    construct_stage(&mut c.program, &words),
+                    String::from(line_og),
+                    line_number,
+                )?;
+            } else if words[0] == "end" {
+                c.state = State::None;
+                check_jumps(&c.program, &c.context)?;
+            } else if !words[0].is_empty() {
+                error_detail!(
+                    format!("Unknown command \"{}\"", words[0]),
+                    ConstructorErrorType::UnknownCommandError,
+                    line_number,
+                    String::from(line_og)
+                );
+            }
+        }
+        State::RuleAccum(t) => {
+            if words[0] == "rule" {
+                c.rule_accum_depth += 1;
+                c.rule_accum.push(line);
+                if t == RuleBlockType::Rule {
+                    c.rule_accum_broken = true;
                     error_detail!(
-                        format!("Unknown command \"{}\"", words[0]),
-                        ConstructorErrorType::UnknownCommandError,
+                        "Malformed rule definition; tried to nest rules",
+                        ConstructorErrorType::MalformedDefinition,
                         line_number,
                         String::from(line_og)
                     );
                 }
-            }
-            State::RuleAccum(t) => {
-                if words[0] == "rule" {
-                    if t == RuleBlockType::Rule {
-                        error_detail!(
-                            "Malformed rule definition; tried to nest rules",
-                            ConstructorErrorType::MalformedDefinition,
-                            line_number,
-                            String::from(line_og)
-                        );
-                    } else {
-                        rule_accum_depth += 1;
-                        rule_accum.push(line);
+            } else if words[0] == "end" {
+                if c.rule_accum_depth == 1 {
+                    //Leave the block before constructing it, so a broken block is skipped whole
+                    let rule_accum = std::mem::take(&mut c.rule_accum);
+                    c.state = State::Rules;
+                    c.rule_accum_depth = 0;
+                    if std::mem::take(&mut c.rule_accum_broken) {
+                        return Ok(());
                     }
-                } else if words[0] == "end" {
-                    if rule_accum_depth == 1 {
-                        match t {
-                            RuleBlockType::Rule => handle_err(
-                                construct_rule(&mut program, rule_accum),
-                                String::from(line_og),
-                                line_number,
-                            )?,
-                            RuleBlockType::Sub => handle_err(
-                                construct_sub(&mut program, rule_accum),
+
+                    let header: Vec<&str> = rule_accum[0].split_whitespace().collect();
+                    let persistent = header.contains(&"persistent");
+                    //A persistent rule is kept as a subroutine under a name no sub can have
+                    let sub_name = match t {
+                        RuleBlockType::Rule if persistent => {
+                            format!("persistent rule {}", header.get(1).unwrap_or(&""))
+                        }
+                        _ => String::from(*header.get(1).unwrap_or(&"")),
+                    };
+                    match t {
+                        RuleBlockType::Rule if !persistent => handle_err(
+                            construct_rule(&mut c.program, rule_accum, line_number),
+                            String::from(line_og),
+                            line_number,
+                        )?,
+                        RuleBlockType::Sub => handle_err(
+                            construct_sub(&mut c.program, &sub_name, rule_accum, line_number),
+                            String::from(line_og),
+                            line_number,
+                        )?,
+                        //A persistent rule is run in place like a subx, then again after every later rule
+                        RuleBlockType::Rule | RuleBlockType::SubX => {
+                            handle_err(
+                                construct_sub(&mut c.program, &sub_name, rule_accum, line_number),
                                 String::from(line_og),
                                 line_number,
-                            )?,
-                            RuleBlockType::SubX => {
-                                handle_err(
-                                    construct_sub(&mut program, rule_accum.clone()),
-                                    String::from(line_og),
-                                    line_number,
-                                )?;
-                                construct_call(
-                                    &mut program,
-                                    &vec![
-                                        "call",
-                                        rule_accum[0].split(' ').collect::<Vec<&str>>()[1],
-                                    ],
-                                )?; //If it got to this point, bounds are good
-                            }
+                            )?;
+                            c.program
+                                .rules
+                                .push(create_subroutine_call_rule(sub_name.clone()));
                         }
-                        rule_accum = Vec::new();
-                        current_state = State::Rules;
-                        rule_accum_depth = 0;
-                    } else {
-                        rule_accum_depth -= 1;
-                        rule_accum.push(line);
+                    }
+                    if persistent {
+                        c.program
+                            .persistent_rules
+                            .push((c.program.rules.len(), sub_name));
                     }
                 } else {
-                    rule_accum.push(line);
+                    c.rule_accum_depth -= 1;
+                    c.rule_accum.push(line);
                 }
+            } else {
+                c.rule_accum.push(line);
             }
-            State::Diacritics => {
-                if words[0] == "diacritic" {
-                    handle_err(
-                        construct_diacritic(&mut program, &words),
-                        String::from(line_og),
-                        line_number,
-                    )?;
-                } else if words[0] == "end" {
-                    current_state = State::None;
-                } else if !words[0].is_empty() {
+        }
+        State::Diacritics => {
+            if words[0] == "diacritic" {
+                handle_err(
+                    construct_diacritic(&mut c.program, &words),
+                    String::from(line_og),
+                    line_number,
+                )?;
+            } else if words[0] == "end" {
+                c.state = State::None;
+            } else if !words[0].is_empty() {
+                error_detail!(
+                    format!("Unknown command \"{}\"", words[0]),
+                    ConstructorErrorType::UnknownCommandError,
+                    line_number,
+                    String::from(line_og)
+                );
+            }
+        }
+        State::Classes => {
+            if words[0] == "class" {
+                handle_err(
+                    construct_class(&mut c.program, &words),
+                    String::from(line_og),
+                    line_number,
+                )?;
+            } else if words[0] == "end" {
+                c.state = State::None;
+            } else if !words[0].is_empty() {
+                error_detail!(
+                    format!("Unknown command \"{}\"", words[0]),
+                    ConstructorErrorType::UnknownCommandError,
+                    line_number,
+                    String::from(line_og)
+                );
+            }
+        }
+        State::Romanizer | State::Deromanizer => {
+            if words[0] == "end" {
+                c.state = State::None;
+            } else if !words[0].is_empty() {
+                let rules = match c.state {
+                    State::Romanizer => &mut c.program.romanizer,
+                    _ => &mut c.program.deromanizer,
+                };
+                handle_err(
+                    construct_spelling_rule(rules, line),
+                    String::from(line_og),
+                    line_number,
+                )?;
+            }
+        }
+        State::Syllables => {
+            if words[0] == "onset" || words[0] == "nucleus" || words[0] == "coda" {
+                handle_err(
+                    construct_syllable_pattern(&mut c.program, &words),
+                    String::from(line_og),
+                    line_number,
+                )?;
+            } else if words[0] == "end" {
+                c.state = State::None;
+                if c.program
+                    .syllable_structure
+                    .as_ref()
+                    .unwrap()
+                    .nuclei
+                    .is_empty()
+                {
                     error_detail!(
-                        format!("Unknown command \"{}\"", words[0]),
-                        ConstructorErrorType::UnknownCommandError,
+                        "Syllables section has no nucleus patterns",
+                        ConstructorErrorType::MalformedDefinition,
                         line_number,
                         String::from(line_og)
                     );
                 }
+            } else if !words[0].is_empty() {
+                error_detail!(
+                    format!("Unknown command \"{}\"", words[0]),
+                    ConstructorErrorType::UnknownCommandError,
+                    line_number,
+                    String::from(line_og)
+                );
             }
         }
     }
 
-    match current_state {
+    Ok(())
+}
+
+/// Errors if the program ends inside a section.
+fn check_section_finished(
+    state: State,
+    line_number: u32,
+) -> std::result::Result<(), ConstructorError> {
+    match state {
         State::None => {}
         State::Features => error_detail!(
             "Features section never finishes",
@@ -280,6 +655,30 @@ pub fn construct(input: &str) -> std::result::Result<Program, ConstructorError>
             line_number,
             String::from("EOF")
         ),
+        State::Classes => error_detail!(
+            "Classes section never finishes",
+            ConstructorErrorType::HangingSection,
+            line_number,
+            String::from("EOF")
+        ),
+        State::Syllables => error_detail!(
+            "Syllables section never finishes",
+            ConstructorErrorType::HangingSection,
+            line_number,
+            String::from("EOF")
+        ),
+        State::Romanizer => error_detail!(
+            "Romanizer section never finishes",
+            ConstructorErrorType::HangingSection,
+            line_number,
+            String::from("EOF")
+        ),
+        State::Deromanizer => error_detail!(
+            "Deromanizer section never finishes",
+            ConstructorErrorType::HangingSection,
+            line_number,
+            String::from("EOF")
+        ),
         State::Rules => error_detail!(
             "Rules section never finishes",
             ConstructorErrorType::HangingSection,
@@ -293,11 +692,7 @@ pub fn construct(input: &str) -> std::result::Result<Program, ConstructorError>
             String::from("EOF")
         ),
     }
-
-    let elapsed = now.elapsed();
-    println!("Done loading and constructing program in {:.2?}", elapsed);
-
-    Ok(program)
+    Ok(())
 }
 
 /// This function injects more context into the error messages if there is an error, otherwise passes
@@ -523,39 +918,254 @@ fn construct_diacritic(
     Ok(())
 }
 
+fn construct_syllable_structure(
+    program: &mut Program,
+    line: &[&str],
+) -> std::result::Result<(), ConstructorError> {
+    if program.syllable_structure.is_some() {
+        error!(
+            "Duplicate syllables section",
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+
+    let automatic = match line.len() {
+        1 => Some(true),
+        2 if line[1] == "manual" => Some(false),
+        _ => None,
+    };
+
+    //Set up the structure even if the header is malformed, so the patterns can still be checked
+    program.syllable_structure = Some(Rc::new(create_syllable_structure(
+        automatic.unwrap_or(true),
+    )));
+    if automatic.is_none() {
+        error!(
+            "Malformed syllables section definition",
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+    Ok(())
+}
+
+fn construct_syllable_pattern(
+    program: &mut Program,
+    line: &[&str],
+) -> std::result::Result<(), ConstructorError> {
+    if line.len() < 2 {
+        error!(
+            "Malformed syllable pattern definition",
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+
+    let (pattern, _, _) =
+        construct_enviorment_wing(program, &line[1..].join(" "), Ordering::Forward)?;
+    if line[1..].iter().any(|c| *c == "$" || *c == "$$") {
+        error!(
+            "Malformed syllable pattern definition: Word boundary in syllable pattern",
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+    if pattern.iter().any(|p| p.transparent.is_some()) {
+        error!(
+            "Malformed syllable pattern definition: Long-distance predicates can't be used in syllable patterns",
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+
+    let structure = match Rc::get_mut(program.syllable_structure.as_mut().unwrap()) {
+        Some(v) => v,
+        None => error!(
+            "Malformed syllable pattern definition: Syllable patterns cannot use syllable positions",
+            ConstructorErrorType::MalformedDefinition
+        ),
+    };
+    match line[0] {
+        "onset" => structure.onsets.push(pattern),
+        "nucleus" => structure.nuclei.push(pattern),
+        _ => structure.codas.push(pattern),
+    }
+    Ok(())
+}
+
+fn construct_class(
+    program: &mut Program,
+    line: &[&str],
+) -> std::result::Result<(), ConstructorError> {
+    if line.len() < 3 {
+        error!(
+            "Malformed class definition",
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+    if program.classes.contains_key(line[1]) {
+        error!(
+            format!("Duplicate class \"{}\"", line[1]),
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+
+    //Checked now so mistakes are reported on the class, and so classes can't refer to themselves
+    let definition = line[2..].join(" ");
+    construct_predicate(program, &definition)?;
+
+    program.classes.insert(String::from(line[1]), definition);
+    Ok(())
+}
+
+/// Parses a spelling rule, written `from => to / before _ after`. The enviorment is optional,
+/// `$` marks the edge of the word and `*` as the replacement deletes the text.
+fn construct_spelling_rule(
+    rules: &mut Vec<SpellingRule>,
+    line: &str,
+) -> std::result::Result<(), ConstructorError> {
+    let split: Vec<&str> = line.split("=>").collect();
+    if split.len() != 2 || split[0].trim().is_empty() {
+        error!(
+            "Malformed spelling rule definition",
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+    let from = split[0].trim();
+    let (to, enviorment) = match split[1].split_once('/') {
+        Some((to, enviorment)) => (to.trim(), Some(enviorment)),
+        None => (split[1].trim(), None),
+    };
+    let to = if to == "*" { "" } else { to };
+
+    let (before, after) = match enviorment {
+        Some(v) => match v.split_once('_') {
+            Some((before, after)) if !after.contains('_') => (before.trim(), after.trim()),
+            _ => {
+                error!(
+                    "Malformed spelling rule definition: Enviorment needs exactly one _",
+                    ConstructorErrorType::MalformedDefinition
+                );
+            }
+        },
+        None => ("", ""),
+    };
+    let before_boundary = before.starts_with('$');
+    let after_boundary = after.ends_with('$');
+    let before = before.trim_start_matches('$').trim();
+    let after = after.trim_end_matches('$').trim();
+    if before.contains('$') || after.contains('$') {
+        error!(
+            "Malformed spelling rule definition: Word boundary in middle of enviorment",
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+
+    rules.push(create_spelling_rule(
+        String::from(from),
+        String::from(to),
+        String::from(before),
+        String::from(after),
+        before_boundary,
+        after_boundary,
+    ));
+    Ok(())
+}
+
+fn find_class<'a>(
+    program: &'a Program,
+    name: &str,
+) -> std::result::Result<&'a str, ConstructorError> {
+    match program.classes.get(name) {
+        Some(v) => Ok(v),
+        None => error!(
+            format!("Could not find class \"{}\"", name),
+            ConstructorErrorType::MissingClass
+        ),
+    }
+}
+
+fn construct_stage(
+    program: &mut Program,
+    line: &[&str],
+) -> std::result::Result<(), ConstructorError> {
+    if line.len() != 2 {
+        error!(
+            "Malformed stage definition",
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+    let duplicate = program
+        .rules
+        .iter()
+        .any(|r| matches!(r, Rule::Stage { name } if name == line[1]));
+    if duplicate {
+        error!(
+            format!("Duplicate stage \"{}\"", line[1]),
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+
+    program.rules.push(create_stage_rule(String::from(line[1])));
+    Ok(())
+}
+
+fn construct_syllabify(
+    program: &mut Program,
+    line: &[&str],
+) -> std::result::Result<(), ConstructorError> {
+    if line.len() != 1 {
+        error!(
+            "Malformed syllabify definition",
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+    if program.syllable_structure.is_none() {
+        error!(
+            "Cannot syllabify without a syllables section",
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+
+    program.rules.push(create_syllabify_rule());
+    Ok(())
+}
+
+/// `end_line` is the line number of the `end` closing the subroutine. The subroutine is added
+/// as `name`, which is the name in its header unless it's a persistent rule.
 fn construct_sub(
     program: &mut Program,
+    name: &str,
     lines: Vec<&str>,
+    end_line: u32,
 ) -> std::result::Result<(), ConstructorError> {
+    let start_line = end_line - lines.len() as u32;
     if lines.len() < 2 {
         error!(
             "Malformed subroutine definition",
             ConstructorErrorType::MalformedDefinition
         );
     }
-    let line1: Vec<&str> = lines[0].split(' ').collect();
-    if line1.len() != 2 {
+    let line1: Vec<&str> = lines[0].split_whitespace().collect();
+    if line1.len() < 2 {
         error!(
             "Malformed subroutine definition",
             ConstructorErrorType::MalformedDefinition
         );
     }
 
-    let line2: Vec<&str> = lines[1].split(' ').collect();
+    let line2: Vec<&str> = lines[1].split_whitespace().collect();
 
-    if line2.len() != 2 {
-        //Single block subroutine
-        let to_add = vec![construct_rule_simple(program, lines)?];
-        program.subroutines.insert(String::from(line1[1]), to_add);
-    } else if line2[0] == "rule" {
-        //Multi block subroutine
-        let to_add = construct_multi_block_sub(program, lines)?;
-        program.subroutines.insert(String::from(line1[1]), to_add);
+    if !line2.is_empty() && line2[0] == "rule" {
+        //Multi block subroutine; modifiers other than persistent belong on the inner rules
+        if line1[2..].iter().any(|v| *v != "persistent") {
+            error!(
+                "Malformed subroutine definition",
+                ConstructorErrorType::MalformedDefinition
+            );
+        }
+        let to_add = construct_multi_block_sub(program, lines, start_line)?;
+        program.subroutines.insert(String::from(name), to_add);
     } else {
-        error!(
-            "Malformed subroutine definition",
-            ConstructorErrorType::MalformedDefinition
-        );
+        //Single block subroutine; modifiers on the header apply to the block
+        let to_add = vec![construct_rule_simple(program, lines, start_line)?];
+        program.subroutines.insert(String::from(name), to_add);
     }
 
     Ok(())
@@ -564,6 +1174,7 @@ fn construct_sub(
 fn construct_multi_block_sub(
     program: &mut Program,
     lines: Vec<&str>,
+    start_line: u32,
 ) -> std::result::Result<Vec<Rule>, ConstructorError> {
     let mut state = State::Rules;
     let mut rule_accum: Vec<&str> = Vec::new();
@@ -588,6 +1199,10 @@ fn construct_multi_block_sub(
             State::Features => panic!(),
             State::Symbols => panic!(),
             State::Diacritics => panic!(),
+            State::Syllables => panic!(),
+            State::Classes => panic!(),
+            State::Romanizer => panic!(),
+            State::Deromanizer => panic!(),
             State::Rules => {
                 if words[0] == "rule" {
                     rule_accum.push(f);
@@ -607,7 +1222,20 @@ fn construct_multi_block_sub(
             }
             State::RuleAccum(_) => {
                 if words[0] == "end" {
-                    let to_push = match construct_rule_simple(program, rule_accum) {
+                    //Persistent rules run from the top level, so only a whole sub can be one
+                    if rule_accum[0].split_whitespace().any(|v| v == "persistent") {
+                        let header = line_number - rule_accum.len() as i8;
+                        let mut temp = create_constructor_error_empty(
+                            "Malformed rule definition: Rules inside a subroutine can't be persistent; make the subroutine persistent instead",
+                            line!(),
+                            ConstructorErrorType::MalformedDefinition,
+                        );
+                        temp.line_number_user_program =
+                            LineNumberInformation::Offset(header - lines.len() as i8);
+                        return Err(temp);
+                    }
+                    let rule_line = start_line + line_number as u32 - rule_accum.len() as u32;
+                    let to_push = match construct_rule_simple(program, rule_accum, rule_line) {
                         Ok(v) => v,
                         Err(mut v) => {
                             let offset: i8 = line_number - (lines.len() as i8);
@@ -636,18 +1264,23 @@ fn construct_multi_block_sub(
     Ok(to_return)
 }
 
+/// `end_line` is the line number of the `end` closing the rule.
 fn construct_rule(
     program: &mut Program,
     line: Vec<&str>,
+    end_line: u32,
 ) -> std::result::Result<(), ConstructorError> {
-    let temp = construct_rule_simple(program, line)?;
+    let start_line = end_line - line.len() as u32;
+    let temp = construct_rule_simple(program, line, start_line)?;
     program.rules.push(temp);
     Ok(())
 }
 
+/// `line_number` is the line of the rule header, kept for derivation traces.
 fn construct_rule_simple(
     program: &mut Program,
     line: Vec<&str>,
+    line_number: u32,
 ) -> std::result::Result<Rule, ConstructorError> {
     if line.len() < 2 {
         error!(
@@ -655,7 +1288,7 @@ fn construct_rule_simple(
             ConstructorErrorType::MalformedDefinition
         );
     }
-    let (name, flags) = match construct_rule_header(line[0]) {
+    let (name, flags, percent) = match construct_rule_header(line[0]) {
         Ok(v) => v,
         Err(mut v) => {
             let offset: i8 = -(line.len() as i8);
@@ -667,12 +1300,19 @@ fn construct_rule_simple(
     let mut i: usize = 1;
     let mut rule_bytes: Vec<RuleByte> = Vec::new();
     while i < line.len() {
-        match construct_rule_byte(program, line[i]) {
-            Ok(v1) => {
-                if let Some(v2) = v1 {
-                    rule_bytes.push(v2)
-                }
+        let bytes = construct_rule_bytes(program, line[i]).and_then(|v| {
+            //Rules without sandhi run on each word alone, so a boundary between words can't match
+            if flags & RULE_FLAG_SANDHI == 0 && v.iter().any(|b| has_word_break(&b.enviorment)) {
+                return Err(create_constructor_error_empty(
+                    "Malformed rule definition: Word boundary condition in middle of enviorment; only sandhi rules can match across words",
+                    line!(),
+                    ConstructorErrorType::MalformedDefinition,
+                ));
             }
+            Ok(v)
+        });
+        match bytes {
+            Ok(mut v) => rule_bytes.append(&mut v),
             Err(mut v) => {
                 //The error message is attributed to the end of the statement by default, that is the end statement.
                 //This injects an offset to attribute it to the correct line.
@@ -684,7 +1324,177 @@ fn construct_rule_simple(
         i += 1;
     }
 
-    Ok(create_transformation_rule(name, rule_bytes, flags))
+    Ok(create_transformation_rule(
+        name,
+        rule_bytes,
+        flags,
+        percent,
+        line_number,
+    ))
+}
+
+/// Whether the enviorment, or any of its alternatives or exceptions, has a `$` between words.
+fn has_word_break(enviorment: &Enviorment) -> bool {
+    enviorment
+        .ante
+        .iter()
+        .chain(&enviorment.post)
+        .any(|p| matches!(p.predicate.as_saved(), PredicateRef::WordBoundary(_)))
+        || enviorment.alternatives.iter().any(has_word_break)
+        || enviorment.exceptions.iter().any(has_word_break)
+}
+
+fn construct_rule_bytes(
+    program: &Program,
+    data: &str,
+) -> std::result::Result<Vec<RuleByte>, ConstructorError> {
+    let mut result: Vec<RuleByte> = Vec::new();
+    for x in expand_feature_variables(program, data)? {
+        if let Some(v) = construct_rule_byte(program, &x)? {
+            result.push(v);
+        }
+    }
+    //Every expansion is kept on the first, so the variable is bound where the line matches
+    if result.len() > 1 {
+        let variants: Vec<RuleByte> = result.drain(1..).collect();
+        result[0].variants = variants;
+    }
+    Ok(result)
+}
+
+enum FeatureVariablePiece {
+    Text(String),
+    Variable { variable: char, negated: bool },
+}
+
+/// Expands feature variables like `[αplace]` by writing the rule byte out once for every value
+/// the variables can take. A variable has the same value everywhere it appears in the byte, so
+/// it can carry a feature from the enviorment to the target or the other way around.
+fn expand_feature_variables(
+    program: &Program,
+    data: &str,
+) -> std::result::Result<Vec<String>, ConstructorError> {
+    let mut pieces: Vec<FeatureVariablePiece> = Vec::new();
+    let mut variables: Vec<(char, Vec<String>)> = Vec::new();
+
+    let mut text = String::new();
+    let mut token = String::new();
+    let mut in_brackets = false;
+    for c in data.chars() {
+        if in_brackets && (c.is_whitespace() || c == ']') {
+            match construct_feature_variable(program, &token, &mut variables)? {
+                Some((variable, negated)) => {
+                    pieces.push(FeatureVariablePiece::Text(std::mem::take(&mut text)));
+                    pieces.push(FeatureVariablePiece::Variable { variable, negated });
+                }
+                None => text += &token,
+            }
+            token.clear();
+            text.push(c);
+            in_brackets = c != ']';
+        } else if in_brackets {
+            token.push(c);
+        } else {
+            text.push(c);
+            in_brackets = c == '[';
+        }
+    }
+    text += &token;
+    pieces.push(FeatureVariablePiece::Text(text));
+
+    let mut combinations: usize = 1;
+    for (_, values) in &variables {
+        combinations = combinations.saturating_mul(values.len());
+    }
+    if combinations > MAX_FEATURE_VARIABLE_EXPANSIONS {
+        error!(
+            "Too many combinations of feature variable values",
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+
+    let mut result: Vec<String> = Vec::with_capacity(combinations);
+    for n in 0..combinations {
+        //Treat n as a mixed radix number with one digit per variable
+        let mut remaining = n;
+        let mut chosen: HashMap<char, usize> = HashMap::new();
+        for (variable, values) in &variables {
+            chosen.insert(*variable, remaining % values.len());
+            remaining /= values.len();
+        }
+
+        let mut expanded = String::new();
+        for piece in &pieces {
+            match piece {
+                FeatureVariablePiece::Text(v) => expanded += v,
+                FeatureVariablePiece::Variable { variable, negated } => {
+                    let (_, values) = variables.iter().find(|x| x.0 == *variable).unwrap();
+                    let mut index = chosen[variable];
+                    if *negated {
+                        index = 1 - index;
+                    }
+                    expanded += &values[index];
+                }
+            }
+        }
+        result.push(expanded);
+    }
+
+    Ok(result)
+}
+
+/// Reads a token like `αvoice` or `-αvoice` from inside a feature matrix, recording the values
+/// the variable can take. Returns `None` for ordinary feature names.
+fn construct_feature_variable(
+    program: &Program,
+    token: &str,
+    variables: &mut Vec<(char, Vec<String>)>,
+) -> std::result::Result<Option<(char, bool)>, ConstructorError> {
+    let negated = token.starts_with('-');
+    let mut chars = token.trim_start_matches(['+', '-']).chars();
+    let variable = match chars.next() {
+        Some(c) if ('α'..='ω').contains(&c) => c,
+        _ => return Ok(None),
+    };
+    let name = chars.as_str();
+    if name.is_empty() {
+        return Ok(None);
+    }
+
+    let feature = match program.names_to_idx.get(name) {
+        Some(id) => program.idx_to_features.get(id).unwrap(),
+        None => error!(
+            format!("Could not find feature {}", name),
+            ConstructorErrorType::MissingFeature
+        ),
+    };
+    let (values, is_bool) = match feature {
+        Feature::SwitchType(data) => (data.option_names.clone(), false),
+        Feature::FeatureDef(data) => (data.option_names.clone(), data.is_bool),
+    };
+    if negated && !is_bool {
+        error!(
+            format!(
+                "Cannot negate the variable for non-boolean feature {}",
+                name
+            ),
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+
+    match variables.iter().find(|x| x.0 == variable) {
+        Some((_, existing)) if *existing != values => error!(
+            format!(
+                "Feature variable {} is used with more than one feature",
+                variable
+            ),
+            ConstructorErrorType::MalformedDefinition
+        ),
+        Some(_) => {}
+        None => variables.push((variable, values)),
+    }
+
+    Ok(Some((variable, negated)))
 }
 
 fn construct_rule_byte(
@@ -726,26 +1536,19 @@ fn construct_rule_byte(
     let mut temp = regex.replace_all(result, String::from_utf8(vec![0]).unwrap());
     let result_split: Vec<&str> = temp.to_mut().split('\u{0000}').collect();
 
-    if predicate_split.len() != result_split.len() {
-        error!(
-            "Predicate and result have a different number of elements on rule",
-            ConstructorErrorType::MalformedDefinition
-        )
-    }
-
-    if predicate_split.len() > 1 {
-        let mut i: usize = 0;
+    if predicate_split.len() > 1 || predicate_split.len() != result_split.len() {
         let mut predicates: Vec<PredicateDef> = Vec::new();
         let mut results: Vec<ResultDef> = Vec::new();
-        while i < predicate_split.len() {
-            predicates.push(construct_predicate(program, predicate_split[i])?);
-            results.push(construct_result(program, result_split[i])?);
-            i += 1;
+        for x in &predicate_split {
+            predicates.push(construct_predicate(program, x)?);
+        }
+        for x in &result_split {
+            results.push(construct_result(program, x)?);
         }
 
         //Make same captures have matching masks.
         //Avoids tedious rewriting
-        i = 0;
+        let mut i: usize = 0;
         while i < predicates.len() {
             if i == 0 {
                 i += 1;
@@ -770,10 +1573,14 @@ fn construct_rule_byte(
             i += 1;
         }
 
+        let enviorment = construct_enviorment(program, enviorment, inverted)?;
+        if predicates.len() != results.len() {
+            return Ok(Some(create_rewrite_rule_byte(
+                predicates, results, enviorment,
+            )?));
+        }
         Ok(Some(create_multi_rule_byte(
-            predicates,
-            results,
-            construct_enviorment(program, enviorment, inverted)?,
+            predicates, results, enviorment,
         )?))
     } else {
         Ok(Some(create_rule_byte(
@@ -790,7 +1597,7 @@ fn construct_predicate(
 ) -> std::result::Result<PredicateDef, ConstructorError> {
     let mut input = predicate.trim();
 
-    let mut captures: Vec<(usize, u64)> = Vec::new();
+    let mut captures: Vec<(usize, LetterValue)> = Vec::new();
     if input.contains('$') {
         let temp: Vec<&str> = input.split('$').collect();
         input = temp[0];
@@ -802,6 +1609,10 @@ fn construct_predicate(
         }
     }
 
+    if let Some(name) = input.strip_prefix('@') {
+        let (predicates, _) = construct_predicate(program, find_class(program, name)?)?;
+        return Ok((predicates, captures));
+    }
     if input == "*" {
         return Ok((Vec::new(), Vec::new()));
     }
@@ -852,7 +1663,7 @@ fn construct_predicates(
 fn construct_capture(
     program: &Program,
     capture: &str,
-) -> std::result::Result<(usize, u64), ConstructorError> {
+) -> std::result::Result<(usize, LetterValue), ConstructorError> {
     if capture.contains('(') {
         if !capture.ends_with(')') {
             error!(
@@ -865,7 +1676,7 @@ fn construct_capture(
 
         let features = split[1].trim_end_matches(')');
         let feature_names: Vec<&str> = features.split(' ').collect();
-        let mut mask: u64 = 0;
+        let mut mask: LetterValue = 0;
 
         for x in feature_names {
             let id = program.names_to_idx.get(x);
@@ -880,7 +1691,7 @@ fn construct_capture(
                 }
             };
 
-            let offset = 64 - feature.start_byte() - feature.length();
+            let offset = LETTER_BITS - feature.start_byte() - feature.length();
             let single_mask = ((2 << (feature.length() - 1)) - 1) << offset;
             mask |= single_mask;
         }
@@ -898,7 +1709,7 @@ fn construct_capture(
     } else {
         let temp = capture.parse::<usize>();
         match temp {
-            Ok(val) => Ok((val, 0xFFFFFFFFFFFFFFFF)),
+            Ok(val) => Ok((val, LetterValue::MAX)),
             Err(_) => {
                 error!(
                     format!("Could not read capture id {}", capture),
@@ -913,6 +1724,13 @@ pub(crate) fn construct_simple_predicate(
     program: &Program,
     predicate: &str,
 ) -> std::result::Result<Box<dyn Predicate>, ConstructorError> {
+    if let Some((inner, position)) = predicate.rsplit_once(':') {
+        return construct_syllable_position_predicate(program, inner, position);
+    }
+    if let Some(name) = predicate.strip_prefix('@') {
+        let (predicates, _) = construct_predicate(program, find_class(program, name)?)?;
+        return Ok(Box::new(create_multi_predicate(predicates, false)));
+    }
     if predicate.starts_with('[') && predicate.ends_with(']') {
         if predicate.contains('!') {
             let (mask, key, masks, keys) = parse_features_negative(program, predicate)?;
@@ -946,7 +1764,7 @@ pub(crate) fn construct_simple_predicate(
                     ConstructorErrorType::MalformedDefinition
                 );
             }
-            let predicate = create_simple_predicate(v[0].value, 0xFFFFFFFFFFFFFFFF);
+            let predicate = create_simple_predicate(v[0].value, LetterValue::MAX);
             Ok(Box::new(predicate))
         }
         Err(v) => error!(
@@ -956,6 +1774,84 @@ pub(crate) fn construct_simple_predicate(
     }
 }
 
+fn construct_syllable_position_predicate(
+    program: &Program,
+    predicate: &str,
+    position: &str,
+) -> std::result::Result<Box<dyn Predicate>, ConstructorError> {
+    let inner: Box<dyn Predicate> = if predicate.is_empty() {
+        Box::new(create_multi_predicate(Vec::new(), true))
+    } else {
+        construct_simple_predicate(program, predicate)?
+    };
+
+    let position = match position {
+        "onset" => SyllablePosition::Onset,
+        "nucleus" => SyllablePosition::Nucleus,
+        "coda" => SyllablePosition::Coda,
+        "initial" => SyllablePosition::Initial,
+        "final" => SyllablePosition::Final,
+        _ => {
+            let (stress, tone) = parse_suprasegmental(position)?;
+            return Ok(Box::new(create_suprasegmental_predicate(
+                inner, stress, tone,
+            )));
+        }
+    };
+
+    let structure = program.syllable_structure.clone();
+    if structure.is_none()
+        && matches!(
+            position,
+            SyllablePosition::Onset | SyllablePosition::Nucleus | SyllablePosition::Coda
+        )
+    {
+        error!(
+            "Cannot match onset, nucleus or coda positions without a syllables section",
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+
+    Ok(Box::new(create_syllable_position_predicate(
+        inner, position, structure,
+    )))
+}
+
+fn parse_suprasegmental(
+    input: &str,
+) -> std::result::Result<(Option<Stress>, Option<u16>), ConstructorError> {
+    match input {
+        "stressed" | "ˈ" => return Ok((Some(Stress::Primary), None)),
+        "secondary" | "ˌ" => return Ok((Some(Stress::Secondary), None)),
+        "unstressed" => return Ok((Some(Stress::Unstressed), None)),
+        "toneless" => return Ok((None, Some(0))),
+        _ => {}
+    }
+
+    let mut tone: u16 = 0;
+    for c in input.chars() {
+        let level = match TONE_LETTERS.iter().position(|x| *x == c) {
+            Some(i) => i as u16 + 1,
+            None => match c {
+                '1'..='5' => c.to_digit(10).unwrap() as u16,
+                _ => error!(
+                    format!("Unknown syllable position or suprasegmental \"{}\"", input),
+                    ConstructorErrorType::MalformedDefinition
+                ),
+            },
+        };
+        tone = tone * 10 + level;
+    }
+    if input.is_empty() || input.chars().count() > MAX_TONE_LEVELS {
+        error!(
+            format!("Malformed tone \"{}\"", input),
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+
+    Ok((None, Some(tone)))
+}
+
 fn construct_result(
     program: &Program,
     result: &str,
@@ -974,6 +1870,10 @@ fn construct_result(
         }
     }
 
+    if let Some(name) = input.strip_prefix('@') {
+        let (results, _) = construct_result(program, find_class(program, name)?)?;
+        return Ok((results, captures));
+    }
     if input.starts_with('{') && input.ends_with('}') {
         input = input.trim_end_matches('}').trim_start_matches('{');
         let results = construct_results(program, input)?;
@@ -1010,6 +1910,34 @@ fn construct_single_result(
     program: &Program,
     result: &str,
 ) -> std::result::Result<Box<dyn Result>, ConstructorError> {
+    if let Some((inner, suprasegmental)) = result.rsplit_once(':') {
+        let (stress, tone) = parse_suprasegmental(suprasegmental)?;
+        let inner = if inner.is_empty() {
+            None
+        } else {
+            Some(construct_single_result(program, inner)?)
+        };
+        return Ok(Box::new(create_suprasegmental_result(inner, stress, tone)));
+    }
+    if let Some(reference) = result.strip_prefix('%') {
+        let digits = reference.len()
+            - reference
+                .trim_start_matches(|c: char| c.is_ascii_digit())
+                .len();
+        let position = match reference[..digits].parse::<usize>() {
+            Ok(v) if v > 0 => v - 1,
+            _ => error!(
+                format!("Malformed position reference \"{}\"", result),
+                ConstructorErrorType::MalformedDefinition
+            ),
+        };
+        let inner = if digits == reference.len() {
+            None
+        } else {
+            Some(construct_single_result(program, &reference[digits..])?)
+        };
+        return Ok(Box::new(create_position_result(position, inner)));
+    }
     if result.starts_with(">[") || result.starts_with('[') && result.ends_with(']') {
         if result.starts_with('>') {
             let temp = result.trim_start_matches('>');
@@ -1055,7 +1983,7 @@ fn construct_single_result(
 fn parse_features_simple(
     program: &Program,
     features: &str,
-) -> std::result::Result<(u64, u64), ConstructorError> {
+) -> std::result::Result<(LetterValue, LetterValue), ConstructorError> {
     let mut feature = features;
     if feature.starts_with('[') {
         feature = feature.trim_start_matches('[');
@@ -1066,8 +1994,8 @@ fn parse_features_simple(
 
     let params: Vec<&str> = feature.split_whitespace().collect();
 
-    let mut mask: u64 = 0;
-    let mut key: u64 = 0;
+    let mut mask: LetterValue = 0;
+    let mut key: LetterValue = 0;
 
     let mut i: usize = 0;
 
@@ -1086,9 +2014,9 @@ fn parse_features_simple(
 
         let feature = program.idx_to_features.get(idx).unwrap();
 
-        let offset = 64 - feature.start_byte() - feature.length();
+        let offset = LETTER_BITS - feature.start_byte() - feature.length();
         mask |= ((2 << (feature.length() - 1)) - 1) << offset;
-        key |= (*index as u64) << offset;
+        key |= (*index as LetterValue) << offset;
 
         i += 1;
     }
@@ -1096,10 +2024,57 @@ fn parse_features_simple(
     Ok((mask, key))
 }
 
+/// Constructs an enviorment from a list of alternatives separated by commas, optionally
+/// followed by `unless` and a list of exceptions.
 fn construct_enviorment(
     program: &Program,
     enviorment: &str,
     inverted: bool,
+) -> std::result::Result<Enviorment, ConstructorError> {
+    let padded = format!(" {} ", enviorment);
+    let split: Vec<&str> = padded.split(" unless ").collect();
+    if split.len() > 2 {
+        error!(
+            "Malformed enviorment definition: Multiple unless clauses",
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+
+    let mut alternatives: Vec<Enviorment> = Vec::new();
+    let clauses: Vec<&str> = split[0].split(',').collect();
+    for clause in &clauses {
+        //An empty alternative would match everywhere, making the whole rule unconditional
+        if clauses.len() > 1 && clause.trim().is_empty() {
+            error!(
+                "Malformed enviorment definition: Empty enviorment alternative",
+                ConstructorErrorType::MalformedDefinition
+            );
+        }
+        alternatives.push(construct_enviorment_clause(program, clause.trim())?);
+    }
+    let mut exceptions: Vec<Enviorment> = Vec::new();
+    if split.len() == 2 {
+        for clause in split[1].split(',') {
+            if clause.trim().is_empty() {
+                error!(
+                    "Malformed enviorment definition: Empty unless clause",
+                    ConstructorErrorType::MalformedDefinition
+                );
+            }
+            exceptions.push(construct_enviorment_clause(program, clause.trim())?);
+        }
+    }
+
+    let mut result = alternatives.remove(0);
+    result.inverted = inverted;
+    result.alternatives = alternatives;
+    result.exceptions = exceptions;
+    Ok(result)
+}
+
+fn construct_enviorment_clause(
+    program: &Program,
+    enviorment: &str,
 ) -> std::result::Result<Enviorment, ConstructorError> {
     if enviorment.is_empty() {
         return Ok(create_empty_enviorment());
@@ -1119,25 +2094,25 @@ fn construct_enviorment(
         );
     }
 
-    let (ante_wing, ante_boundary) =
+    let (ante_wing, ante_boundary, ante_phrase_boundary) =
         construct_enviorment_wing(program, enviorment_wings[0], Ordering::Reverse)?;
-    let (post_wing, post_boundary) =
+    let (post_wing, post_boundary, post_phrase_boundary) =
         construct_enviorment_wing(program, enviorment_wings[1], Ordering::Forward)?;
 
-    Ok(create_enviorment(
-        ante_wing,
-        post_wing,
-        ante_boundary,
-        post_boundary,
-        inverted,
-    ))
+    let mut result = create_enviorment(ante_wing, post_wing, ante_boundary, post_boundary, false);
+    result.ante_phrase_boundary = ante_phrase_boundary;
+    result.post_phrase_boundary = post_phrase_boundary;
+    Ok(result)
 }
 
+/// Returns the predicates of the wing, nearest first, and whether it ends at a word boundary
+/// (`$`) or at a phrase boundary (`$$`). A `$` with more of the wing after it only matches a
+/// gap between words.
 fn construct_enviorment_wing(
     program: &Program,
     enviorment: &str,
     direction: Ordering,
-) -> std::result::Result<(Vec<EnviormentPredicate>, bool), ConstructorError> {
+) -> std::result::Result<(Vec<EnviormentPredicate>, bool, bool), ConstructorError> {
     let working_value = enviorment.trim();
 
     let regex = Regex::new(r" (?![^(]*\))(?![^\[]*\])").unwrap();
@@ -1151,31 +2126,52 @@ fn construct_enviorment_wing(
     let mut result: Vec<EnviormentPredicate> = Vec::new();
 
     let mut flag = false;
+    let mut phrase_flag = false;
 
     for c in enviorment_components {
         if c.is_empty() {
             continue;
         }
+        if phrase_flag {
+            error!(
+                "Malformed rule definition: Phrase boundary condition in middle of enviorment",
+                ConstructorErrorType::MalformedDefinition
+            );
+        }
+        if c == "$$" {
+            phrase_flag = true;
+            continue;
+        }
+        if flag {
+            result.push(create_zero_width_enviorment_predicate(Box::new(
+                create_word_boundary_predicate(),
+            )));
+            flag = false;
+        }
         if c == "$" {
             flag = true;
+        } else if c == "." {
+            result.push(create_zero_width_enviorment_predicate(Box::new(
+                create_syllable_boundary_predicate(),
+            )));
         } else {
-            if flag {
-                error!(
-                    "Malformed rule definition: Word boundary condition in middle of enviorment",
-                    ConstructorErrorType::MalformedDefinition
-                );
-            }
             result.push(construct_enviorment_predicate(program, c)?);
         }
     }
 
-    Ok((result, flag))
+    Ok((result, flag || phrase_flag, phrase_flag))
 }
 
 fn construct_enviorment_predicate(
     program: &Program,
     predicate: &str,
 ) -> std::result::Result<EnviormentPredicate, ConstructorError> {
+    if let Some((trigger, transparent)) = predicate.split_once('~') {
+        return Ok(create_nonlocal_enviorment_predicate(
+            construct_simple_predicate(program, trigger)?,
+            construct_simple_predicate(program, transparent)?,
+        ));
+    }
     if predicate.contains('<') {
         let predicate_split: Vec<&str> = predicate.split('<').collect();
         if predicate_split.len() != 2 {
@@ -1238,7 +2234,7 @@ fn construct_enviorment_predicate(
     Ok(create_enviorment_predicate_single(predicate_instance))
 }
 
-fn construct_rule_header(data: &str) -> std::result::Result<(String, u16), ConstructorError> {
+fn construct_rule_header(data: &str) -> std::result::Result<(String, u16, u8), ConstructorError> {
     let words: Vec<&str> = data.split_whitespace().collect();
 
     if words.len() < 2 {
@@ -1257,7 +2253,64 @@ fn construct_rule_header(data: &str) -> std::result::Result<(String, u16), Const
         );
     }
 
-    Ok((name, 0)) //TODO add flags
+    let mut flags: u16 = 0;
+    let mut percent: u8 = 100;
+    let mut modifiers = words[2..].iter();
+    while let Some(modifier) = modifiers.next() {
+        let flag = match *modifier {
+            "ltr" => 0,
+            "rtl" => RULE_FLAG_RTL,
+            "first" => RULE_FLAG_FIRST,
+            "last" => RULE_FLAG_LAST,
+            "repeat" => RULE_FLAG_REPEAT,
+            "persistent" => RULE_FLAG_PERSISTENT,
+            "sandhi" => RULE_FLAG_SANDHI,
+            "sporadic" | "diffuse" => {
+                percent = match modifiers
+                    .next()
+                    .map(|v| v.trim_end_matches('%').parse::<u8>())
+                {
+                    Some(Ok(v)) if v <= 100 => v,
+                    _ => {
+                        error!(
+                            format!(
+                                "Rule modifier \"{}\" needs a percentage from 0 to 100",
+                                modifier
+                            ),
+                            ConstructorErrorType::MalformedDefinition
+                        );
+                    }
+                };
+                if *modifier == "sporadic" {
+                    RULE_FLAG_SPORADIC
+                } else {
+                    RULE_FLAG_DIFFUSE
+                }
+            }
+            _ => {
+                error!(
+                    format!("Unknown rule modifier \"{}\"", modifier),
+                    ConstructorErrorType::UnknownCommandError
+                );
+            }
+        };
+        flags |= flag;
+    }
+
+    if flags & RULE_FLAG_FIRST != 0 && flags & RULE_FLAG_LAST != 0 {
+        error!(
+            "Rule cannot be both first and last match only",
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+    if flags & RULE_FLAG_SPORADIC != 0 && flags & RULE_FLAG_DIFFUSE != 0 {
+        error!(
+            "Rule cannot be both sporadic and diffuse",
+            ConstructorErrorType::MalformedDefinition
+        );
+    }
+
+    Ok((name, flags, percent))
 }
 
 fn construct_symbol(
@@ -1293,7 +2346,10 @@ fn construct_symbol(
 fn parse_features_negative(
     program: &Program,
     features: &str,
-) -> std::result::Result<(u64, u64, Vec<u64>, Vec<u64>), ConstructorError> {
+) -> std::result::Result<
+    (LetterValue, LetterValue, Vec<LetterValue>, Vec<LetterValue>),
+    ConstructorError,
+> {
     let mut feature = features;
     if feature.starts_with('[') {
         feature = feature.trim_start_matches('[');
@@ -1304,12 +2360,12 @@ fn parse_features_negative(
 
     let params: Vec<&str> = feature.split_whitespace().collect();
 
-    let mut mask: u64 = 0;
-    let mut key: u64 = 0;
-    let mut masks: Vec<u64> = Vec::new();
-    let mut keys: Vec<u64> = Vec::new();
+    let mut mask: LetterValue = 0;
+    let mut key: LetterValue = 0;
+    let mut masks: Vec<LetterValue> = Vec::new();
+    let mut keys: Vec<LetterValue> = Vec::new();
 
-    let mut validation_key: u64 = 0;
+    let mut validation_key: LetterValue = 0;
 
     let mut i: usize = 0;
 
@@ -1332,14 +2388,14 @@ fn parse_features_negative(
 
         validation_key |= feature.validation_key();
 
-        let offset = 64 - feature.start_byte() - feature.length();
+        let offset = LETTER_BITS - feature.start_byte() - feature.length();
 
         if flag {
             masks.push(((2 << (feature.length() - 1)) - 1) << offset);
-            keys.push((*index as u64) << offset);
+            keys.push((*index as LetterValue) << offset);
         } else {
             mask |= ((2 << (feature.length() - 1)) - 1) << offset;
-            key |= (*index as u64) << offset;
+            key |= (*index as LetterValue) << offset;
 
             mask |= feature.validation_mask();
             key |= feature.validation_key();
@@ -1371,7 +2427,7 @@ fn parse_features_negative(
 pub(crate) fn parse_features(
     program: &Program,
     features: &str,
-) -> std::result::Result<(u64, u64), ConstructorError> {
+) -> std::result::Result<(LetterValue, LetterValue), ConstructorError> {
     let mut feature = features;
     if feature.starts_with('[') {
         feature = feature.trim_start_matches('[');
@@ -1382,10 +2438,10 @@ pub(crate) fn parse_features(
 
     let params: Vec<&str> = feature.split_whitespace().collect();
 
-    let mut mask: u64 = 0;
-    let mut key: u64 = 0;
+    let mut mask: LetterValue = 0;
+    let mut key: LetterValue = 0;
 
-    let mut validation_key: u64 = 0;
+    let mut validation_key: LetterValue = 0;
 
     let mut i: usize = 0;
 
@@ -1407,9 +2463,9 @@ pub(crate) fn parse_features(
         mask |= feature.validation_mask();
         key |= feature.validation_key();
 
-        let offset = 64 - feature.start_byte() - feature.length();
+        let offset = LETTER_BITS - feature.start_byte() - feature.length();
         mask |= ((2 << (feature.length() - 1)) - 1) << offset;
-        key |= (*index as u64) << offset;
+        key |= (*index as LetterValue) << offset;
 
         validation_key |= feature.validation_key();
 
@@ -1496,8 +2552,8 @@ fn construct_validation_masks(program: &mut Program) {
 
 fn construct_validation_masks_recurse(
     features: &mut Vec<Feature>,
-    current_validation_mask: u64,
-    current_validation_key: u64,
+    current_validation_mask: LetterValue,
+    current_validation_key: LetterValue,
 ) {
     let mut i: usize = 0;
     while i < features.len() {
@@ -1509,9 +2565,10 @@ fn construct_validation_masks_recurse(
 
                 let mut j: usize = 0;
                 while j < data.features.len() {
-                    let mask =
-                        ((2 << data.self_length) - 1) << (64 - data.start_byte - data.self_length);
-                    let key = (j as u64 + 1) << (64 - data.start_byte - data.self_length);
+                    let mask = ((2 << data.self_length) - 1)
+                        << (LETTER_BITS - data.start_byte - data.self_length);
+                    let key = (j as LetterValue + 1)
+                        << (LETTER_BITS - data.start_byte - data.self_length);
 
                     let temp_validation_key = current_validation_key | key;
                     let temp_validation_mask = current_validation_mask | mask;
@@ -1725,6 +2782,15 @@ fn calculate_offsets_recurse(
                 Feature::SwitchType(data)
             }
             Feature::FeatureDef(mut data) => {
+                if current_offset as u16 + data.length as u16 > LETTER_BITS as u16 {
+                    error!(
+                        format!(
+                            "Feature \"{}\" does not fit in {} bits",
+                            data.name, LETTER_BITS
+                        ),
+                        ConstructorErrorType::FeatureOverflow
+                    );
+                }
                 data.start_byte = current_offset;
                 current_offset += data.length;
                 Feature::FeatureDef(data)
@@ -1753,13 +2819,14 @@ fn bump_offsets_recurse(
                 Feature::SwitchType(data)
             }
             Feature::FeatureDef(mut data) => {
-                data.start_byte += amount;
-                if data.start_byte + data.length >= 64 {
+                if data.start_byte as u16 + amount as u16 + data.length as u16 > LETTER_BITS as u16
+                {
                     error!(
                         "Couldn't bump feature",
                         ConstructorErrorType::FeatureOverflow
                     );
                 }
+                data.start_byte += amount;
                 Feature::FeatureDef(data)
             }
         };
@@ -1,23 +1,40 @@
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, VecDeque};
 use std::fmt;
+use std::rc::Rc;
 
+use super::rules::{PredicateRef, ResultRef};
 use crate::manual_ux::project::Project;
 use crate::{priority_queue::PriorityQueue, websocket_handler::WebSocketResponse};
 
-pub type PredicateDef = (Vec<Box<dyn Predicate>>, Vec<(usize, u64)>);
+/// The integer backing a letter's features. Every feature is laid out as a bit
+/// range inside this value, so its width caps the size of the feature tree.
+pub type LetterValue = u128;
+pub const LETTER_BITS: u8 = LetterValue::BITS as u8;
+
+pub type PredicateDef = (Vec<Box<dyn Predicate>>, Vec<(usize, LetterValue)>);
 pub type ResultDef = (Vec<Box<dyn Result>>, Vec<usize>);
 
+#[derive(Serialize, Deserialize)]
 pub struct Program {
     pub features: Vec<Feature>,
     pub diacritics: Vec<Diacritic>,
     pub rules: Vec<Rule>,
     pub subroutines: HashMap<String, Vec<Rule>>,
     pub labels: HashMap<String, usize>,
+    pub persistent_rules: Vec<(usize, String)>,
+    pub syllable_structure: Option<Rc<SyllableStructure>>,
+    /// Named classes from the `classes` section, kept as source text and parsed wherever
+    /// `@name` is used, so the same class works as a predicate, result or enviorment.
+    pub classes: HashMap<String, String>,
+    /// Turns phonemic output into spelling, from the `romanizer` section.
+    pub romanizer: Vec<SpellingRule>,
+    /// Turns spelling into phonemic input, from the `deromanizer` section.
+    pub deromanizer: Vec<SpellingRule>,
     pub names_to_idx: HashMap<String, u32>,
     pub idx_to_features: HashMap<u32, Feature>,
     pub features_to_idx: HashMap<String, (u32, usize)>,
-    pub symbol_to_letter: HashMap<String, (Letter, u64)>,
+    pub symbol_to_letter: HashMap<String, (Letter, LetterValue)>,
     pub letter_to_symbol: HashMap<Letter, String>,
 }
 
@@ -25,25 +42,48 @@ pub struct Program {
 pub struct Word {
     pub letters: Vec<Letter>,
     pub syllables: Vec<SyllableDefinition>,
+    /// Where each word of a phrase after the first starts, written as spaces. Empty for a
+    /// single word.
+    pub word_breaks: Vec<usize>,
+    /// Whether the ends of the word are also the ends of the phrase. Only false for words that
+    /// were split out of a phrase.
+    pub phrase_initial: bool,
+    pub phrase_final: bool,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct SyllableDefinition {
     pub start: usize,
     pub end: usize,
+    pub stress: Stress,
+    /// The tone contour as pitch levels from 1 (low) to 5 (high), one decimal digit per level,
+    /// so 51 is a falling tone. 0 means the syllable has no tone.
+    pub tone: u16,
+}
+
+/// Syllable stress, written before the syllable as ˈ for primary or ˌ for secondary stress.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Stress {
+    Unstressed,
+    Secondary,
+    Primary,
 }
 
+/// Chao tone letters, indexed by pitch level minus one.
+pub const TONE_LETTERS: [char; 5] = ['˩', '˨', '˧', '˦', '˥'];
+pub const MAX_TONE_LEVELS: usize = 4;
+
 pub struct ProgramCreationContext {
     pub rule_line_defs: HashMap<usize, u32>,
 }
 
-#[derive(Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub enum Feature {
     SwitchType(SwitchType),
     FeatureDef(FeatureDef),
 }
 
-#[derive(Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SwitchType {
     pub start_byte: u8,
     pub tot_length: u8,
@@ -52,11 +92,11 @@ pub struct SwitchType {
     pub name: String,
     pub option_names: Vec<String>,
     pub id: u32,
-    pub validation_mask: u64,
-    pub validation_key: u64,
+    pub validation_mask: LetterValue,
+    pub validation_key: LetterValue,
 }
 
-#[derive(Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct FeatureDef {
     pub start_byte: u8,
     pub length: u8,
@@ -64,8 +104,8 @@ pub struct FeatureDef {
     pub option_names: Vec<String>,
     pub is_bool: bool,
     pub id: u32,
-    pub validation_mask: u64,
-    pub validation_key: u64,
+    pub validation_mask: LetterValue,
+    pub validation_key: LetterValue,
 }
 
 impl Word {
@@ -88,6 +128,11 @@ impl Word {
 
     pub fn insert(&mut self, index: usize, element: Letter) {
         self.letters.insert(index, element);
+        for x in &mut self.word_breaks {
+            if *x > index {
+                *x += 1;
+            }
+        }
         for x in &mut self.syllables {
             if x.start > index {
                 x.start += 1;
@@ -98,8 +143,35 @@ impl Word {
         }
     }
 
+    /// Inserts a letter directly after the letter at `index`, in the same syllable as it.
+    pub fn insert_after(&mut self, index: usize, element: Letter) {
+        self.letters.insert(index + 1, element);
+        for x in &mut self.word_breaks {
+            if *x > index {
+                *x += 1;
+            }
+        }
+        for x in &mut self.syllables {
+            if x.start > index {
+                x.start += 1;
+            }
+            if x.end > index {
+                x.end += 1;
+            }
+        }
+    }
+
     pub fn remove(&mut self, index: usize) {
         self.letters.remove(index);
+        for x in &mut self.word_breaks {
+            if *x > index {
+                *x -= 1;
+            }
+        }
+        //Drop the breaks around words that are now empty
+        let len = self.letters.len();
+        self.word_breaks.retain(|x| *x != 0 && *x != len);
+        self.word_breaks.dedup();
         for x in &mut self.syllables {
             if x.start > index {
                 x.start -= 1;
@@ -120,6 +192,110 @@ impl Word {
             }
         }
     }
+
+    /// Returns the syllable containing the letter at `position`. A word without syllables is
+    /// treated as a single syllable.
+    pub fn syllable_at(&self, position: usize) -> SyllableDefinition {
+        for x in &self.syllables {
+            if x.start <= position && position < x.end {
+                return *x;
+            }
+        }
+        SyllableDefinition {
+            start: 0,
+            end: self.len(),
+            stress: Stress::Unstressed,
+            tone: 0,
+        }
+    }
+
+    /// Whether there is a syllable boundary in the gap before `index`. Both ends of the word
+    /// count as boundaries.
+    pub fn is_syllable_boundary(&self, index: usize) -> bool {
+        index == 0
+            || index >= self.len()
+            || self.word_breaks.contains(&index)
+            || self
+                .syllables
+                .iter()
+                .any(|x| x.start == index || x.end == index)
+    }
+
+    /// Whether the gap before `index` is between two words of the phrase or at either end.
+    pub fn is_word_boundary(&self, index: usize) -> bool {
+        index == 0 || index >= self.len() || self.word_breaks.contains(&index)
+    }
+
+    /// Whether the gap before `index` is at the start of one of the words of the phrase.
+    pub fn is_word_start(&self, index: usize) -> bool {
+        index == 0 || self.word_breaks.contains(&index)
+    }
+
+    /// Whether the gap before `index` is at the end of one of the words of the phrase.
+    pub fn is_word_end(&self, index: usize) -> bool {
+        index >= self.len() || self.word_breaks.contains(&index)
+    }
+
+    /// Whether the gap before `index` is at the start of the whole phrase.
+    pub fn is_phrase_start(&self, index: usize) -> bool {
+        index == 0 && self.phrase_initial
+    }
+
+    /// Whether the gap before `index` is at the end of the whole phrase.
+    pub fn is_phrase_end(&self, index: usize) -> bool {
+        index >= self.len() && self.phrase_final
+    }
+
+    /// Splits a phrase into its words. Syllables are cut at the word breaks.
+    pub fn split_words(&self) -> Vec<Word> {
+        let mut starts: Vec<usize> = vec![0];
+        starts.extend(&self.word_breaks);
+        let mut result: Vec<Word> = Vec::with_capacity(starts.len());
+        for (i, start) in starts.iter().enumerate() {
+            let end = starts.get(i + 1).copied().unwrap_or(self.len());
+            let mut word = create_word(self.letters[*start..end].to_vec());
+            for x in &self.syllables {
+                let (s, e) = (x.start.max(*start), x.end.min(end));
+                if s < e {
+                    word.syllables.push(SyllableDefinition {
+                        start: s - start,
+                        end: e - start,
+                        ..*x
+                    });
+                }
+            }
+            word.phrase_initial = i == 0 && self.phrase_initial;
+            word.phrase_final = end == self.len() && self.phrase_final;
+            result.push(word);
+        }
+        result
+    }
+}
+
+/// Joins words back into one phrase, the reverse of `Word::split_words`. Words that have
+/// become empty are dropped.
+pub fn join_words(words: Vec<Word>) -> Word {
+    let mut result = create_empty_word();
+    result.phrase_initial = words.first().is_none_or(|v| v.phrase_initial);
+    result.phrase_final = words.last().is_none_or(|v| v.phrase_final);
+    for word in words {
+        if word.is_empty() {
+            continue;
+        }
+        let offset = result.len();
+        if offset != 0 {
+            result.word_breaks.push(offset);
+        }
+        result
+            .syllables
+            .extend(word.syllables.iter().map(|x| SyllableDefinition {
+                start: x.start + offset,
+                end: x.end + offset,
+                ..*x
+            }));
+        result.letters.extend(word.letters);
+    }
+    result
 }
 
 impl SyllableDefinition {
@@ -177,13 +353,13 @@ impl Feature {
             Feature::FeatureDef(data) => data.id,
         }
     }
-    pub fn validation_key(&self) -> u64 {
+    pub fn validation_key(&self) -> LetterValue {
         match self {
             Feature::SwitchType(data) => data.validation_key,
             Feature::FeatureDef(data) => data.validation_key,
         }
     }
-    pub fn validation_mask(&self) -> u64 {
+    pub fn validation_mask(&self) -> LetterValue {
         match self {
             Feature::SwitchType(data) => data.validation_mask,
             Feature::FeatureDef(data) => data.validation_mask,
@@ -209,9 +385,9 @@ impl Feature {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct Letter {
-    pub value: u64,
+    pub value: LetterValue,
 }
 
 impl Letter {
@@ -220,15 +396,17 @@ impl Letter {
         match temp {
             Some(result) => Ok(result.to_string()),
             None => {
-                let mut queue: PriorityQueue<(u64, u64, &str), i8> = PriorityQueue::new();
+                let mut queue: PriorityQueue<(LetterValue, LetterValue, &str), i8> =
+                    PriorityQueue::new();
                 queue.push((self.value, self.value, ""), 0);
-                let mut completed_nodes: HashMap<u64, (u64, &str)> = HashMap::new();
+                let mut completed_nodes: HashMap<LetterValue, (LetterValue, &str)> = HashMap::new();
                 let mut depth: u16 = 0;
                 while depth < 1024 {
                     if queue.is_empty() {
                         return Err(ApplicationError::IntoConversionError(format!(
-                            "Could not find matching symbol for {:#066b}",
-                            self.value
+                            "Could not find matching symbol for {:#0width$b}",
+                            self.value,
+                            width = LETTER_BITS as usize + 2
                         )));
                     }
                     let ((value, prev_node, current_symbol), priority) = queue.pop().unwrap();
@@ -259,8 +437,9 @@ impl Letter {
                             depth2 += 1;
                         }
                         return Err(ApplicationError::IntoConversionError(format!(
-                            "Could not find matching symbol for {:#066b}",
-                            self.value
+                            "Could not find matching symbol for {:#0width$b}",
+                            self.value,
+                            width = LETTER_BITS as usize + 2
                         )));
                     }
 
@@ -268,8 +447,9 @@ impl Letter {
                 }
 
                 Err(ApplicationError::IntoConversionError(format!(
-                    "Could not find matching symbol for {:#066b}",
-                    self.value
+                    "Could not find matching symbol for {:#0width$b}",
+                    self.value,
+                    width = LETTER_BITS as usize + 2
                 )))
             }
         }
@@ -278,37 +458,98 @@ impl Letter {
 
 pub trait Predicate {
     fn validate(&self, word: &Word, position: usize) -> bool;
+
+    /// Whether the predicate could match the letter in some word, for finding rules that can
+    /// never apply. Predicates that depend on more than the letter can always match.
+    fn could_match(&self, _letter: &Letter) -> bool {
+        true
+    }
+
+    /// The predicate as it is saved in a cached program.
+    fn as_saved(&self) -> PredicateRef<'_>;
+
+    /// Points the predicate and any it holds at the program's syllable structure, which a
+    /// cached program doesn't save inside its predicates.
+    fn share_structure(&mut self, _structure: &Rc<SyllableStructure>) {}
 }
 
 pub trait Result {
     fn transform(&self, input: &Letter) -> Option<Letter>;
+
+    /// Applies any syllable-level changes to the syllable holding the transformed letter.
+    fn transform_syllable(&self, _syllable: &mut SyllableDefinition) {}
+
+    /// The index of the matched letter this result transforms, for results that move letters
+    /// around. `None` transforms the letter in the result's own slot.
+    fn source(&self) -> Option<usize> {
+        None
+    }
+
+    /// The result as it is saved in a cached program.
+    fn as_saved(&self) -> ResultRef<'_>;
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Transformation {
     pub predicate: Vec<Box<dyn Predicate>>,
     pub result: Vec<Box<dyn Result>>,
-    pub predicate_captures: Vec<(usize, u64)>,
+    pub predicate_captures: Vec<(usize, LetterValue)>,
     pub result_captures: Vec<usize>,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct RuleByte {
     pub transformations: Vec<Transformation>,
     pub enviorment: Enviorment,
     pub num_captures: usize,
+    /// Results for a rule byte whose result has a different number of letters than its
+    /// predicate. The matched letters are replaced by these; empty for every other rule byte.
+    pub rewrite: Vec<ResultDef>,
+    /// The same line written out with the other values of its feature variables, like
+    /// `[αplace]`. At each position the first of this byte and its variants that matches is
+    /// applied, so together they act as a single line.
+    pub variants: Vec<RuleByte>,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum JumpCondition {
     PrevMod,
     Flag,
     Unconditional,
 }
 
+//Flags set by modifiers in a rule header, i.e. `rule name rtl repeat`
+pub const RULE_FLAG_RTL: u16 = 1;
+pub const RULE_FLAG_FIRST: u16 = 1 << 1;
+pub const RULE_FLAG_LAST: u16 = 1 << 2;
+pub const RULE_FLAG_REPEAT: u16 = 1 << 3;
+pub const RULE_FLAG_PERSISTENT: u16 = 1 << 4;
+//Sporadic rules apply at a fraction of matching sites, diffuse rules to a fraction of words
+pub const RULE_FLAG_SPORADIC: u16 = 1 << 5;
+pub const RULE_FLAG_DIFFUSE: u16 = 1 << 6;
+/// Applies the rule to a phrase as a whole instead of to each word, so it can match across
+/// word breaks.
+pub const RULE_FLAG_SANDHI: u16 = 1 << 7;
+
+/// Decides which words or sites a sporadic or diffuse rule applies to. The choice comes from
+/// a hash of the word and rule name, so the same input always gives the same output.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Chance {
+    pub seed: u64,
+    /// Out of 100; 100 always applies.
+    pub percent: u8,
+}
+
+#[derive(Serialize, Deserialize)]
 pub enum Rule {
     TransformationRule {
         bytes: Vec<RuleByte>,
         flags: u16,
         name: String,
+        /// The percentage of words or sites a sporadic or diffuse rule applies to.
+        percent: u8,
+        /// The line of the rule header in the source program.
+        line_number: u32,
     },
     CallSubroutine {
         name: String,
@@ -322,27 +563,81 @@ pub enum Rule {
         predicate: Vec<Box<dyn Predicate>>,
         enviorment: Enviorment,
     },
+    Syllabify,
+    /// Marks a named intermediate form of the word, recorded by `Program::apply_with_stages`.
+    Stage {
+        name: String,
+    },
 }
 
+/// A predicate with a max quantity of zero takes up no letters. It is checked against the gap
+/// before the given position instead, which may be the gap at the end of the word.
+#[derive(Serialize, Deserialize)]
 pub struct EnviormentPredicate {
     pub predicate: Box<dyn Predicate>,
     pub min_quant: u8,
     pub max_quant: u8,
+    /// Letters to look past when searching for this predicate, for long-distance enviorments.
+    /// The nearest letter that isn't transparent has to match the predicate.
+    pub transparent: Option<Box<dyn Predicate>>,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Enviorment {
     pub ante: Vec<EnviormentPredicate>,
     pub post: Vec<EnviormentPredicate>,
     pub ante_word_boundary: bool,
     pub post_word_boundary: bool,
+    /// Set by `$$`, which only matches at the ends of the whole phrase.
+    pub ante_phrase_boundary: bool,
+    pub post_phrase_boundary: bool,
     pub inverted: bool,
+    /// Other enviorments that also allow the rule to apply, written after commas.
+    pub alternatives: Vec<Enviorment>,
+    /// Enviorments written after `unless` that block the rule even if an enviorment matches.
+    pub exceptions: Vec<Enviorment>,
+}
+
+/// A line of a `romanizer` or `deromanizer` section, replacing the text `from` with `to` when
+/// it comes right after `before` and right before `after`. The boundary flags require the
+/// context to also be at the edge of the word.
+#[derive(Serialize, Deserialize)]
+pub struct SpellingRule {
+    pub from: String,
+    pub to: String,
+    pub before: String,
+    pub after: String,
+    pub before_boundary: bool,
+    pub after_boundary: bool,
+}
+
+/// The patterns declared in a `syllables` section. Each pattern is a sequence of predicates
+/// with quantifiers, like an enviorment wing, and must cover its part of the syllable exactly.
+/// An empty list of onsets or codas means any margin is allowed.
+#[derive(Serialize, Deserialize)]
+pub struct SyllableStructure {
+    pub onsets: Vec<Vec<EnviormentPredicate>>,
+    pub nuclei: Vec<Vec<EnviormentPredicate>>,
+    pub codas: Vec<Vec<EnviormentPredicate>>,
+    pub automatic: bool,
 }
 
+/// Where in a syllable a letter has to be for a `:position` predicate to match.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SyllablePosition {
+    Onset,
+    Nucleus,
+    Coda,
+    Initial,
+    Final,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Diacritic {
     pub diacritic: String,
-    pub mask: u64,
-    pub key: u64,
-    pub mod_key: u64,
+    pub mask: LetterValue,
+    pub key: LetterValue,
+    pub mod_key: LetterValue,
 }
 
 #[derive(PartialEq)]
@@ -367,6 +662,11 @@ pub fn create_empty_program() -> Program {
         rules: Vec::new(),
         subroutines: HashMap::new(),
         labels: HashMap::new(),
+        persistent_rules: Vec::new(),
+        syllable_structure: None,
+        classes: HashMap::new(),
+        romanizer: Vec::new(),
+        deromanizer: Vec::new(),
         names_to_idx: HashMap::new(),
         idx_to_features: HashMap::new(),
         features_to_idx: HashMap::new(),
@@ -375,6 +675,24 @@ pub fn create_empty_program() -> Program {
     }
 }
 
+pub fn create_spelling_rule(
+    from: String,
+    to: String,
+    before: String,
+    after: String,
+    before_boundary: bool,
+    after_boundary: bool,
+) -> SpellingRule {
+    SpellingRule {
+        from,
+        to,
+        before,
+        after,
+        before_boundary,
+        after_boundary,
+    }
+}
+
 pub fn create_program_creation_context() -> ProgramCreationContext {
     ProgramCreationContext {
         rule_line_defs: HashMap::new(),
@@ -434,7 +752,12 @@ pub fn create_switch_type(
     }
 }
 
-pub fn create_diacritic(diacritic: String, mask: u64, key: u64, mod_key: u64) -> Diacritic {
+pub fn create_diacritic(
+    diacritic: String,
+    mask: LetterValue,
+    key: LetterValue,
+    mod_key: LetterValue,
+) -> Diacritic {
     Diacritic {
         diacritic,
         mask,
@@ -505,6 +828,9 @@ pub fn create_rule_byte(
         }
     }
 
+    let slots = if predicate.0.is_empty() { 0 } else { 1 };
+    check_result_sources(&result.0, slots)?;
+
     Ok(RuleByte {
         transformations: vec![Transformation {
             predicate: predicate.0,
@@ -514,6 +840,8 @@ pub fn create_rule_byte(
         }],
         enviorment,
         num_captures: num_captures + 1,
+        rewrite: Vec::new(),
+        variants: Vec::new(),
     })
 }
 
@@ -530,6 +858,7 @@ pub fn create_multi_rule_byte(
         ));
     }
     let mut transformations: Vec<Transformation> = Vec::new();
+    let predicate_len = predicate.len();
 
     let mut i: usize = 0;
     while i < predicate.len() {
@@ -559,7 +888,9 @@ pub fn create_multi_rule_byte(
     }
 
     i = 0;
+    let slots = predicate_len;
     for r in result {
+        check_result_sources(&r.0, slots)?;
         transformations[i].result = r.0;
 
         for x in &r.1 {
@@ -580,11 +911,112 @@ pub fn create_multi_rule_byte(
         transformations,
         enviorment,
         num_captures: num_captures + 1,
+        rewrite: Vec::new(),
+        variants: Vec::new(),
     })
 }
 
-pub fn create_transformation_rule(name: String, bytes: Vec<RuleByte>, flags: u16) -> Rule {
-    Rule::TransformationRule { bytes, flags, name }
+pub fn create_rewrite_rule_byte(
+    predicate: Vec<PredicateDef>,
+    result: Vec<ResultDef>,
+    enviorment: Enviorment,
+) -> std::result::Result<RuleByte, ConstructorError> {
+    let slots = predicate.len();
+    if predicate.iter().any(|p| p.0.is_empty()) {
+        return Err(create_constructor_error_empty(
+            "Cannot insert more than one letter with a single predicate",
+            line!(),
+            ConstructorErrorType::MalformedDefinition,
+        ));
+    }
+
+    let empty: Vec<ResultDef> = (0..slots).map(|_| (Vec::new(), Vec::new())).collect();
+    let mut rule_byte = create_multi_rule_byte(predicate, empty, enviorment)?;
+
+    for r in &result {
+        check_result_sources(&r.0, slots)?;
+        for x in &r.1 {
+            if *x >= rule_byte.num_captures {
+                return Err(create_constructor_error_empty(
+                    "More output captures than input captures",
+                    line!(),
+                    ConstructorErrorType::MalformedDefinition,
+                ));
+            }
+        }
+    }
+    rule_byte.rewrite = result;
+
+    Ok(rule_byte)
+}
+
+fn check_result_sources(
+    result: &[Box<dyn Result>],
+    slots: usize,
+) -> std::result::Result<(), ConstructorError> {
+    for x in result {
+        if let Some(source) = x.source() {
+            if source >= slots {
+                return Err(create_constructor_error_empty(
+                    format!("Position reference %{} is out of range", source + 1),
+                    line!(),
+                    ConstructorErrorType::MalformedDefinition,
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn create_transformation_rule(
+    name: String,
+    bytes: Vec<RuleByte>,
+    flags: u16,
+    percent: u8,
+    line_number: u32,
+) -> Rule {
+    Rule::TransformationRule {
+        bytes,
+        flags,
+        name,
+        percent,
+        line_number,
+    }
+}
+
+/// Seeds the chance from the rule name and the symbols of the word, not the letters' feature
+/// bits, so changing the `feature_def` layout doesn't change which words are picked.
+pub fn create_chance(
+    program: &Program,
+    word: &Word,
+    name: &str,
+    percent: u8,
+) -> std::result::Result<Chance, ApplicationError> {
+    let mut symbols = String::new();
+    for letter in &word.letters {
+        symbols.push_str(&letter.get_symbol(program)?);
+    }
+
+    let seed = hash_fnv1a(name.bytes().chain(symbols.bytes()));
+    Ok(Chance { seed, percent })
+}
+
+/// FNV-1a, which unlike the standard library hasher gives the same hash on every release and
+/// platform, so it is used for anything that is saved or has to be reproducible.
+pub fn hash_fnv1a<I: IntoIterator<Item = u8>>(bytes: I) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub fn create_certain_chance() -> Chance {
+    Chance {
+        seed: 0,
+        percent: 100,
+    }
 }
 
 pub fn create_subroutine_call_rule(name: String) -> Rule {
@@ -599,6 +1031,10 @@ pub fn create_jump_rule(name: String, condition: JumpCondition, inverted: bool)
     }
 }
 
+pub fn create_stage_rule(name: String) -> Rule {
+    Rule::Stage { name }
+}
+
 pub fn create_detect_rule(predicate: Vec<Box<dyn Predicate>>, enviorment: Enviorment) -> Rule {
     Rule::Detect {
         predicate,
@@ -606,13 +1042,53 @@ pub fn create_detect_rule(predicate: Vec<Box<dyn Predicate>>, enviorment: Envior
     }
 }
 
+pub fn create_syllabify_rule() -> Rule {
+    Rule::Syllabify
+}
+
+pub fn create_syllable_structure(automatic: bool) -> SyllableStructure {
+    SyllableStructure {
+        onsets: Vec::new(),
+        nuclei: Vec::new(),
+        codas: Vec::new(),
+        automatic,
+    }
+}
+
+pub fn create_zero_width_enviorment_predicate(
+    predicate: Box<dyn Predicate>,
+) -> EnviormentPredicate {
+    EnviormentPredicate {
+        predicate,
+        min_quant: 0,
+        max_quant: 0,
+        transparent: None,
+    }
+}
+
+pub fn create_nonlocal_enviorment_predicate(
+    predicate: Box<dyn Predicate>,
+    transparent: Box<dyn Predicate>,
+) -> EnviormentPredicate {
+    EnviormentPredicate {
+        predicate,
+        min_quant: 1,
+        max_quant: 1,
+        transparent: Some(transparent),
+    }
+}
+
 pub fn create_empty_enviorment() -> Enviorment {
     Enviorment {
         ante: Vec::new(),
         post: Vec::new(),
         ante_word_boundary: false,
         post_word_boundary: false,
+        ante_phrase_boundary: false,
+        post_phrase_boundary: false,
         inverted: false,
+        alternatives: Vec::new(),
+        exceptions: Vec::new(),
     }
 }
 
@@ -628,7 +1104,11 @@ pub fn create_enviorment(
         post,
         ante_word_boundary,
         post_word_boundary,
+        ante_phrase_boundary: false,
+        post_phrase_boundary: false,
         inverted,
+        alternatives: Vec::new(),
+        exceptions: Vec::new(),
     }
 }
 
@@ -637,6 +1117,7 @@ pub fn create_enviorment_predicate_single(predicate: Box<dyn Predicate>) -> Envi
         predicate,
         min_quant: 1,
         max_quant: 1,
+        transparent: None,
     }
 }
 
@@ -649,21 +1130,40 @@ pub fn create_enviorment_predicate(
         predicate,
         min_quant: min,
         max_quant: max,
+        transparent: None,
     }
 }
 
 pub fn to_string(program: &Program, word: Word) -> std::result::Result<String, ApplicationError> {
     let mut result = String::from("");
     for (index, l) in word.letters.iter().enumerate() {
+        if word.word_breaks.contains(&index) {
+            result += " ";
+        } else {
+            for x in &word.syllables {
+                if (x.start == index || x.end == index) && index != 0 && index != word.len() {
+                    result += ".";
+                    //We only place on dot per syllable boundary, but two will match;
+                    //the end of the prev and the start of the next
+                    break;
+                }
+            }
+        }
         for x in &word.syllables {
-            if (x.start == index || x.end == index) && index != 0 && index != word.len() {
-                result += ".";
-                //We only place on dot per syllable boundary, but two will match;
-                //the end of the prev and the start of the next
-                break;
+            if x.start == index && x.end > index {
+                match x.stress {
+                    Stress::Primary => result += "ˈ",
+                    Stress::Secondary => result += "ˌ",
+                    Stress::Unstressed => {}
+                }
             }
         }
         result += &l.get_symbol(program)?;
+        for x in &word.syllables {
+            if x.end == index + 1 && x.end > x.start {
+                result += &tone_to_string(x.tone);
+            }
+        }
     }
     Ok(result)
 }
@@ -674,6 +1174,20 @@ pub struct ExecutionContext {
     pub mod_flag: bool,
     pub flag_flag: bool,
     pub jump_flag: bool,
+    /// Names of rules that are skipped for this word, for lexical exceptions.
+    pub exceptions: Vec<String>,
+    /// The word at every stage marker reached so far.
+    pub stages: Vec<(String, Word)>,
+    /// The steps of the derivation so far, if it's being traced.
+    pub trace: Option<Vec<DerivationStep>>,
+}
+
+/// A rule that changed the word while tracing, and the word after it applied.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DerivationStep {
+    pub rule: String,
+    pub line_number: u32,
+    pub result: String,
 }
 
 pub fn create_execution_context(result: &Word) -> ExecutionContext {
@@ -683,6 +1197,9 @@ pub fn create_execution_context(result: &Word) -> ExecutionContext {
         mod_flag: false,
         flag_flag: false,
         jump_flag: false,
+        exceptions: Vec::new(),
+        stages: Vec::new(),
+        trace: None,
     }
 }
 
@@ -735,6 +1252,8 @@ where
         line_number_user_program: LineNumberInformation::Raw(line_number_user_program),
         line_number_code,
         error_type,
+        file: None,
+        column_span: None,
     }
 }
 
@@ -751,6 +1270,8 @@ pub fn create_constructor_error_offset(
         line_number_user_program: LineNumberInformation::Offset(line_number_user_program),
         line_number_code,
         error_type,
+        file: None,
+        column_span: None,
     }
 }
 
@@ -768,25 +1289,27 @@ where
         line_number_user_program: LineNumberInformation::Undetermined,
         line_number_code,
         error_type,
+        file: None,
+        column_span: None,
     }
 }
 
 pub fn create_word_syllables(letters: Vec<Letter>, syllables: Vec<SyllableDefinition>) -> Word {
-    Word { letters, syllables }
+    Word {
+        letters,
+        syllables,
+        word_breaks: Vec::new(),
+        phrase_initial: true,
+        phrase_final: true,
+    }
 }
 
 pub fn create_empty_word() -> Word {
-    Word {
-        letters: Vec::new(),
-        syllables: Vec::new(),
-    }
+    create_word(Vec::new())
 }
 
 pub fn create_word(letters: Vec<Letter>) -> Word {
-    Word {
-        letters,
-        syllables: Vec::new(),
-    }
+    create_word_syllables(letters, Vec::new())
 }
 
 pub fn create_syllable_definition(
@@ -799,7 +1322,23 @@ pub fn create_syllable_definition(
         )));
     }
 
-    Ok(SyllableDefinition { start, end })
+    Ok(SyllableDefinition {
+        start,
+        end,
+        stress: Stress::Unstressed,
+        tone: 0,
+    })
+}
+
+/// Writes a tone contour with tone letters, or nothing for a toneless syllable.
+pub fn tone_to_string(tone: u16) -> String {
+    let mut levels: Vec<char> = Vec::new();
+    let mut remaining = tone;
+    while remaining > 0 {
+        levels.push(TONE_LETTERS[(remaining % 10) as usize - 1]);
+        remaining /= 10;
+    }
+    levels.iter().rev().collect()
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -809,6 +1348,11 @@ pub struct ConstructorError {
     pub line_number_user_program: LineNumberInformation,
     pub line_number_code: u32,
     pub error_type: ConstructorErrorType,
+    /// The included file the error is in, or `None` for the file being constructed.
+    pub file: Option<String>,
+    /// The columns of the line the error is about, counted in characters from 0 with the end
+    /// excluded. `None` when the error isn't about a single line.
+    pub column_span: Option<(u32, u32)>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -824,6 +1368,8 @@ pub enum ConstructorErrorType {
     ParseError,
     MissingSubroutine,
     MissingLabel,
+    MissingClass,
+    MissingInclude,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
@@ -847,6 +1393,8 @@ impl fmt::Display for ConstructorError {
             ConstructorErrorType::ParseError => "ParseError",
             ConstructorErrorType::MissingSubroutine => "MissingSubroutine",
             ConstructorErrorType::MissingLabel => "MissingLabel",
+            ConstructorErrorType::MissingClass => "MissingClass",
+            ConstructorErrorType::MissingInclude => "MissingInclude",
         };
         write_constructor_error(
             f,
@@ -855,6 +1403,7 @@ impl fmt::Display for ConstructorError {
             &self.line_contents,
             self.line_number_user_program,
             self.line_number_code,
+            &self.file,
         )
         .expect("Error formatting error message");
         Ok(())
@@ -868,6 +1417,7 @@ fn write_constructor_error(
     line_contents: &String,
     line_number_user_program: LineNumberInformation,
     line_number_code: u32,
+    file: &Option<String>,
 ) -> std::result::Result<(), std::fmt::Error> {
     let line_number: u32 = match line_number_user_program {
         LineNumberInformation::Undetermined => {
@@ -881,9 +1431,14 @@ fn write_constructor_error(
         LineNumberInformation::Raw(v) => v,
     };
 
+    let location = match file {
+        Some(v) => format!("line {} of {}", line_number, v),
+        None => format!("line {}", line_number),
+    };
+
     write!(
         formatter,
-        "{}({}: Line {}) on line {}; {}",
-        type_message, error_message, line_number_code, line_number, line_contents
+        "{}({}: Line {}) on {}; {}",
+        type_message, error_message, line_number_code, location, line_contents
     )
 }
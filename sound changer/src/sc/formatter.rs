@@ -0,0 +1,217 @@
+const INDENT: &str = "    ";
+
+enum Section {
+    None,
+    Rules,
+    /// Inside this many nested `rule`, `sub` or `subx` blocks.
+    RuleBlock(usize),
+    Spelling,
+    Other,
+}
+
+enum Code {
+    Text(String),
+    RuleByte {
+        predicate: String,
+        result: String,
+        /// The slash before the enviorment, and the enviorment.
+        enviorment: Option<(&'static str, String)>,
+        /// Rule lines next to each other share a group, and are lined up together.
+        group: usize,
+    },
+}
+
+struct FormattedLine {
+    depth: usize,
+    code: Code,
+    comment: Option<String>,
+}
+
+/// Writes a program back out with one space between words, four spaces of indentation for
+/// every level of section or rule block, and the `=>` and `/` of neighbouring rule lines lined
+/// up.
+/// Comments are kept as they are. The result constructs to the same program as the input.
+pub fn format_program(input: &str) -> String {
+    let mut section = Section::None;
+    let mut group_count: usize = 0;
+    let mut lines: Vec<Option<FormattedLine>> = Vec::new();
+
+    for line in input.lines() {
+        let (code, comment) = match line.find('#') {
+            Some(i) => (line[..i].trim(), Some(String::from(line[i..].trim_end()))),
+            None => (line.trim(), None),
+        };
+        let in_group = matches!(
+            lines.last(),
+            Some(Some(FormattedLine {
+                code: Code::RuleByte { .. },
+                ..
+            }))
+        );
+        if code.is_empty() && comment.is_none() {
+            lines.push(None);
+            continue;
+        }
+        let first = code.split_whitespace().next().unwrap_or("");
+        let is_end = first == "end";
+
+        let depth = match &section {
+            Section::None => 0,
+            Section::RuleBlock(v) if is_end => *v,
+            Section::RuleBlock(v) => v + 1,
+            _ if is_end => 0,
+            _ => 1,
+        };
+        let text = match section {
+            //Spelling rules replace text, so the spacing inside them is kept
+            Section::Spelling => String::from(code),
+            Section::None if first == "include" => {
+                format!("include {}", code["include".len()..].trim())
+            }
+            _ => collapse_spaces(code),
+        };
+        let code = match &section {
+            Section::RuleBlock(_) if first != "rule" && !is_end => match split_rule_byte(&text) {
+                Some((predicate, result, enviorment)) => {
+                    if !in_group {
+                        group_count += 1;
+                    }
+                    Code::RuleByte {
+                        predicate,
+                        result,
+                        enviorment,
+                        group: group_count,
+                    }
+                }
+                None => Code::Text(text),
+            },
+            _ => Code::Text(text),
+        };
+        lines.push(Some(FormattedLine {
+            depth,
+            code,
+            comment,
+        }));
+
+        //Sections only start at the top level, the same as in the constructor
+        section = match section {
+            Section::None => match first {
+                "rules" => Section::Rules,
+                "romanizer" | "deromanizer" => Section::Spelling,
+                "feature_def" | "symbols" | "diacritics" | "classes" | "syllables" => {
+                    Section::Other
+                }
+                _ => Section::None,
+            },
+            Section::Rules => match first {
+                "end" => Section::None,
+                "rule" | "sub" | "subx" => Section::RuleBlock(1),
+                _ => Section::Rules,
+            },
+            Section::RuleBlock(1) if is_end => Section::Rules,
+            Section::RuleBlock(v) if is_end => Section::RuleBlock(v - 1),
+            Section::RuleBlock(v) if first == "rule" => Section::RuleBlock(v + 1),
+            Section::Spelling | Section::Other if is_end => Section::None,
+            v => v,
+        };
+    }
+
+    let mut predicate_widths: Vec<usize> = vec![0; group_count + 1];
+    let mut result_widths: Vec<usize> = vec![0; group_count + 1];
+    for line in lines.iter().flatten() {
+        if let Code::RuleByte {
+            predicate,
+            result,
+            enviorment,
+            group,
+            ..
+        } = &line.code
+        {
+            predicate_widths[*group] = predicate_widths[*group].max(predicate.chars().count());
+            if enviorment.is_some() {
+                result_widths[*group] = result_widths[*group].max(result.chars().count());
+            }
+        }
+    }
+
+    let mut result: Vec<String> = Vec::new();
+    for line in lines {
+        let line = match line {
+            Some(v) => v,
+            //Runs of blank lines become one, and there are none at the start or end
+            None => {
+                if result.last().is_some_and(|v| !v.is_empty()) {
+                    result.push(String::new());
+                }
+                continue;
+            }
+        };
+        let code = match line.code {
+            Code::Text(v) => v,
+            Code::RuleByte {
+                predicate,
+                result,
+                enviorment,
+                group,
+            } => match enviorment {
+                None => format!(
+                    "{:<width$} => {}",
+                    predicate,
+                    result,
+                    width = predicate_widths[group]
+                ),
+                Some((slash, enviorment)) => format!(
+                    "{:<width$} => {:<width2$} {} {}",
+                    predicate,
+                    result,
+                    slash,
+                    enviorment,
+                    width = predicate_widths[group],
+                    width2 = result_widths[group]
+                ),
+            },
+        };
+        let text = match line.comment {
+            Some(comment) if code.is_empty() => comment,
+            Some(comment) => format!("{} {}", code, comment),
+            None => code,
+        };
+        result.push(format!("{}{}", INDENT.repeat(line.depth), text.trim()));
+    }
+    while result.last().is_some_and(|v| v.is_empty()) {
+        result.pop();
+    }
+
+    let mut output = result.join("\n");
+    output.push('\n');
+    output
+}
+
+fn collapse_spaces(code: &str) -> String {
+    code.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Splits a rule line into its predicate, result and enviorment the same way the constructor
+/// does. Returns `None` for lines it would reject.
+#[allow(clippy::type_complexity)]
+fn split_rule_byte(code: &str) -> Option<(String, String, Option<(&'static str, String)>)> {
+    let (predicate, rest) = code.split_once("=>")?;
+    if rest.contains("=>") {
+        return None;
+    }
+    let (result, enviorment) = match rest.split_once("//") {
+        Some((result, enviorment)) => (result, Some(("//", enviorment))),
+        None => match rest.split_once('/') {
+            Some((result, enviorment)) => (result, Some(("/", enviorment))),
+            None => (rest, None),
+        },
+    };
+    if enviorment.is_some_and(|(_, v)| v.contains('/')) {
+        return None;
+    }
+    Some((
+        String::from(predicate.trim()),
+        String::from(result.trim()),
+        enviorment.map(|(slash, v)| (slash, collapse_spaces(v))),
+    ))
+}
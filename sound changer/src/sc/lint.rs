@@ -0,0 +1,364 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+use super::applicator::from_string;
+use super::data::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum LintType {
+    UnmatchablePredicate,
+    UnwritableResult,
+    UnusedLabel,
+    UncalledSubroutine,
+    ShadowedSymbol,
+    RoundTripFailure,
+    AmbiguousSegmentation,
+}
+
+/// A likely mistake in a program that still constructs fine.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LintWarning {
+    pub lint_type: LintType,
+    pub message: String,
+    /// The line of the rule the warning is about, if it is about a rule.
+    pub line_number: Option<u32>,
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line_number {
+            Some(v) => write!(
+                f,
+                "Warning on line {}: {:?} - {}",
+                v, self.lint_type, self.message
+            ),
+            None => write!(f, "Warning: {:?} - {}", self.lint_type, self.message),
+        }
+    }
+}
+
+fn create_lint_warning(
+    lint_type: LintType,
+    message: String,
+    line_number: Option<u32>,
+) -> LintWarning {
+    LintWarning {
+        lint_type,
+        message,
+        line_number,
+    }
+}
+
+impl super::data::Program {
+    /// Looks for likely mistakes: rules that can never match or that produce letters with no
+    /// symbol, labels and subroutines nothing uses, and symbols hidden by another symbol with
+    /// the same features, or that can't be read back the way they are written.
+    pub fn lint(&self) -> Vec<LintWarning> {
+        let mut result: Vec<LintWarning> = Vec::new();
+        self.lint_symbols(&mut result);
+        let inventory = self.letter_inventory();
+        self.lint_round_trip(&inventory, &mut result);
+
+        let mut subroutine_names: Vec<&String> = self.subroutines.keys().collect();
+        subroutine_names.sort();
+        let rules: Vec<&Rule> = self
+            .rules
+            .iter()
+            .chain(subroutine_names.iter().flat_map(|v| &self.subroutines[*v]))
+            .collect();
+
+        for rule in &rules {
+            if let Rule::TransformationRule {
+                bytes,
+                name,
+                line_number,
+                ..
+            } = rule
+            {
+                for byte in bytes
+                    .iter()
+                    .flat_map(|v| std::iter::once(v).chain(&v.variants))
+                {
+                    self.lint_rule_byte(byte, name, *line_number, &inventory, &mut result);
+                }
+            }
+        }
+
+        let mut jump_targets: HashSet<&String> = HashSet::new();
+        let mut called: HashSet<&String> = self.persistent_rules.iter().map(|v| &v.1).collect();
+        for rule in &rules {
+            match rule {
+                Rule::JumpSubRoutine { name, .. } => {
+                    jump_targets.insert(name);
+                }
+                Rule::CallSubroutine { name } => {
+                    called.insert(name);
+                }
+                _ => {}
+            }
+        }
+
+        let mut labels: Vec<&String> = self.labels.keys().collect();
+        labels.sort();
+        for label in labels {
+            if !jump_targets.contains(label) {
+                result.push(create_lint_warning(
+                    LintType::UnusedLabel,
+                    format!("Label \"{}\" is never jumped to", label),
+                    None,
+                ));
+            }
+        }
+        for name in subroutine_names {
+            if !called.contains(name) {
+                let line_number = self.subroutines[name].iter().find_map(|v| match v {
+                    Rule::TransformationRule { line_number, .. } => Some(*line_number),
+                    _ => None,
+                });
+                result.push(create_lint_warning(
+                    LintType::UncalledSubroutine,
+                    format!("Subroutine \"{}\" is never called", name),
+                    line_number,
+                ));
+            }
+        }
+
+        result
+    }
+
+    /// Every letter a word can start with: each symbol, and each symbol with up to two
+    /// diacritics applied.
+    fn letter_inventory(&self) -> Vec<Letter> {
+        let mut symbols: Vec<(&String, Letter)> = self
+            .symbol_to_letter
+            .iter()
+            .map(|(symbol, (letter, _))| (symbol, *letter))
+            .collect();
+        symbols.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut seen: HashSet<Letter> = HashSet::new();
+        let mut result: Vec<Letter> = Vec::new();
+        for (_, letter) in symbols {
+            if seen.insert(letter) {
+                result.push(letter);
+            }
+        }
+        let mut start = 0;
+        for _ in 0..2 {
+            let end = result.len();
+            for i in start..end {
+                for d in &self.diacritics {
+                    if result[i].value & d.mask == d.key {
+                        let letter = Letter {
+                            value: (result[i].value & !d.mask) | d.mod_key,
+                        };
+                        if seen.insert(letter) {
+                            result.push(letter);
+                        }
+                    }
+                }
+            }
+            start = end;
+        }
+        result
+    }
+
+    fn lint_rule_byte(
+        &self,
+        byte: &RuleByte,
+        name: &String,
+        line_number: u32,
+        inventory: &[Letter],
+        result: &mut Vec<LintWarning>,
+    ) {
+        let unmatchable = byte.transformations.iter().any(|t| {
+            t.predicate
+                .iter()
+                .any(|p| !inventory.iter().any(|l| p.could_match(l)))
+        });
+        if unmatchable {
+            result.push(create_lint_warning(
+                LintType::UnmatchablePredicate,
+                format!("A condition in rule \"{}\" matches no symbol", name),
+                Some(line_number),
+            ));
+        }
+
+        for t in &byte.transformations {
+            //Captured features depend on the word, so the result can't be checked alone
+            if !t.result_captures.is_empty() {
+                continue;
+            }
+            let mut outputs: Vec<(Option<Letter>, Option<Letter>)> = Vec::new();
+            if t.predicate.is_empty() {
+                for r in &t.result {
+                    outputs.push((None, r.transform(&Letter { value: 0 })));
+                }
+            }
+            for (j, p) in t.predicate.iter().enumerate() {
+                let r = match t.result.len() {
+                    1 => &t.result[0],
+                    _ => match t.result.get(j) {
+                        Some(v) => v,
+                        None => continue,
+                    },
+                };
+                for letter in inventory.iter().filter(|l| p.could_match(l)) {
+                    outputs.push((Some(*letter), r.transform(letter)));
+                }
+            }
+
+            let unwritable = outputs
+                .into_iter()
+                .find_map(|(input, output)| match output {
+                    Some(v) if v.get_symbol(self).is_err() => Some(input),
+                    _ => None,
+                });
+            if let Some(input) = unwritable {
+                let message = match input.and_then(|v| v.get_symbol(self).ok()) {
+                    Some(v) => format!(
+                        "Rule \"{}\" turns \"{}\" into a letter with no symbol",
+                        name, v
+                    ),
+                    None => format!("Rule \"{}\" inserts a letter with no symbol", name),
+                };
+                result.push(create_lint_warning(
+                    LintType::UnwritableResult,
+                    message,
+                    Some(line_number),
+                ));
+                return;
+            }
+        }
+    }
+
+    /// Symbols with the same features as another symbol are never written, since a letter can
+    /// only have one symbol.
+    fn lint_symbols(&self, result: &mut Vec<LintWarning>) {
+        let mut symbols: Vec<(&String, &Letter)> = self
+            .symbol_to_letter
+            .iter()
+            .map(|(symbol, (letter, _))| (symbol, letter))
+            .collect();
+        symbols.sort_by(|a, b| a.0.cmp(b.0));
+        for (symbol, letter) in symbols {
+            match self.letter_to_symbol.get(letter) {
+                Some(other) if other != symbol => result.push(create_lint_warning(
+                    LintType::ShadowedSymbol,
+                    format!(
+                        "Symbol \"{}\" has the same features as \"{}\", so it is always written as \"{}\"",
+                        symbol, other, other
+                    ),
+                    None,
+                )),
+                _ => {}
+            }
+        }
+    }
+
+    /// Every letter should print to a string which reads back as that same letter, and two
+    /// symbols written next to each other should read back as those two letters. Words which
+    /// don't are silently read as different letters, so rules won't do what they look like.
+    fn lint_round_trip(&self, inventory: &[Letter], result: &mut Vec<LintWarning>) {
+        let mut printed: Vec<(Letter, String)> = Vec::new();
+        let mut failed: Vec<String> = Vec::new();
+        for letter in inventory {
+            let symbol = match letter.get_symbol(self) {
+                //Diacritics on a symbol that already failed would fail the same way
+                Ok(v) if failed.iter().any(|f| v.starts_with(f.as_str())) => continue,
+                Ok(v) => v,
+                Err(e) => {
+                    result.push(create_lint_warning(
+                        LintType::RoundTripFailure,
+                        format!("A letter made with diacritics can't be written: {}", e),
+                        None,
+                    ));
+                    continue;
+                }
+            };
+            let message = match from_string(self, &symbol) {
+                Ok(word) if word.letters == [*letter] => {
+                    printed.push((*letter, symbol));
+                    continue;
+                }
+                Ok(word) => format!(
+                    "\"{}\" is read back as {}",
+                    symbol,
+                    self.describe_letters(&word.letters)
+                ),
+                Err(e) => format!("\"{}\" can't be read back: {}", symbol, e),
+            };
+            result.push(create_lint_warning(
+                LintType::RoundTripFailure,
+                message,
+                None,
+            ));
+            failed.push(symbol);
+        }
+
+        let symbols: Vec<&(Letter, String)> = printed
+            .iter()
+            .filter(|(letter, _)| self.letter_to_symbol.contains_key(letter))
+            .collect();
+        //Words are read longest symbol first, so a pair can only be misread when the first symbol
+        //starts a longer one that the second continues, or the second starts with a diacritic.
+        //Only those pairs are read back, which keeps large inventories fast
+        let keys: Vec<&String> = self.symbol_to_letter.keys().collect();
+        let misreadable: Vec<&(Letter, String)> = symbols
+            .iter()
+            .copied()
+            .filter(|(_, v)| {
+                self.diacritics
+                    .iter()
+                    .any(|d| v.starts_with(d.diacritic.as_str()))
+            })
+            .collect();
+        for (first, first_symbol) in &symbols {
+            let rests: Vec<&str> = keys
+                .iter()
+                .filter(|k| k.len() > first_symbol.len() && k.starts_with(first_symbol.as_str()))
+                .map(|k| &k[first_symbol.len()..])
+                .collect();
+            for (second, second_symbol) in &symbols {
+                let continues = rests
+                    .iter()
+                    .any(|r| r.starts_with(second_symbol.as_str()) || second_symbol.starts_with(r));
+                if !continues && !misreadable.iter().any(|(v, _)| v == second) {
+                    continue;
+                }
+                let string = format!("{}{}", first_symbol, second_symbol);
+                let letters = match from_string(self, &string) {
+                    Ok(v) => v.letters,
+                    Err(_) => Vec::new(),
+                };
+                if letters != [*first, *second] {
+                    result.push(create_lint_warning(
+                        LintType::AmbiguousSegmentation,
+                        format!(
+                            "\"{}\" followed by \"{}\" is read as {}",
+                            first_symbol,
+                            second_symbol,
+                            self.describe_letters(&letters)
+                        ),
+                        None,
+                    ));
+                }
+            }
+        }
+    }
+
+    fn describe_letters(&self, letters: &[Letter]) -> String {
+        if letters.is_empty() {
+            return String::from("nothing");
+        }
+        letters
+            .iter()
+            .map(|l| match l.get_symbol(self) {
+                Ok(v) => format!("\"{}\"", v),
+                Err(_) => String::from("a letter with no symbol"),
+            })
+            .collect::<Vec<String>>()
+            .join(" + ")
+    }
+}
@@ -1,6 +1,9 @@
 pub mod applicator;
+pub mod cache;
 pub mod constructor;
 pub mod data;
+pub mod formatter;
+pub mod lint;
 pub mod rules;
 #[cfg(test)]
 mod tests;
@@ -1,33 +1,164 @@
 use super::data::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::rc::Rc;
 
+#[derive(Serialize, Deserialize)]
 pub struct MultiPredicate {
     pub predicate: Vec<Box<dyn Predicate>>,
     pub req_all: bool,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct SimplePredicate {
-    pub mask: u64,
-    pub key: u64,
+    pub mask: LetterValue,
+    pub key: LetterValue,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct PositiveNegativePredicate {
-    pub positive_mask: u64,
-    pub positive_key: u64,
-    pub negative_masks: Vec<u64>,
-    pub negative_keys: Vec<u64>,
+    pub positive_mask: LetterValue,
+    pub positive_key: LetterValue,
+    pub negative_masks: Vec<LetterValue>,
+    pub negative_keys: Vec<LetterValue>,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct SyllableBoundaryPredicate {}
+
+/// Matches the gap between two words of a phrase, written as `$` inside an enviorment.
+#[derive(Serialize, Deserialize)]
+pub struct WordBoundaryPredicate {}
+
+#[derive(Serialize, Deserialize)]
+pub struct SyllablePositionPredicate {
+    pub predicate: Box<dyn Predicate>,
+    pub position: SyllablePosition,
+    /// Always the program's syllable structure, so it isn't saved with a cached program.
+    #[serde(skip)]
+    pub structure: Option<Rc<SyllableStructure>>,
+}
+
+/// Matches letters whose syllable has the given stress and tone. `None` matches anything.
+#[derive(Serialize, Deserialize)]
+pub struct SuprasegmentalPredicate {
+    pub predicate: Box<dyn Predicate>,
+    pub stress: Option<Stress>,
+    pub tone: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct SimpleResult {
     pub letter: Letter,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct SimpleApplicationResult {
-    pub mask: u64,
-    pub value: u64,
+    pub mask: LetterValue,
+    pub value: LetterValue,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct DeleteResult {}
 
+/// Takes the letter matched at `position` instead of the one in its own slot, so rules can
+/// reorder whole letters. The inner result, if any, is applied on top.
+#[derive(Serialize, Deserialize)]
+pub struct PositionResult {
+    pub position: usize,
+    pub result: Option<Box<dyn Result>>,
+}
+
+/// Transforms the letter with the inner result, if any, then sets the stress and tone of its
+/// syllable.
+#[derive(Serialize, Deserialize)]
+pub struct SuprasegmentalResult {
+    pub result: Option<Box<dyn Result>>,
+    pub stress: Option<Stress>,
+    pub tone: Option<u16>,
+}
+
+/// A predicate as it is saved in a cached program, one variant for every kind of predicate.
+#[derive(Serialize)]
+pub enum PredicateRef<'a> {
+    Multi(&'a MultiPredicate),
+    Simple(&'a SimplePredicate),
+    PositiveNegative(&'a PositiveNegativePredicate),
+    SyllableBoundary(&'a SyllableBoundaryPredicate),
+    WordBoundary(&'a WordBoundaryPredicate),
+    SyllablePosition(&'a SyllablePositionPredicate),
+    Suprasegmental(&'a SuprasegmentalPredicate),
+}
+
+/// The same as `PredicateRef`, but owning the predicate, for loading it back.
+#[derive(Deserialize)]
+enum PredicateData {
+    Multi(MultiPredicate),
+    Simple(SimplePredicate),
+    PositiveNegative(PositiveNegativePredicate),
+    SyllableBoundary(SyllableBoundaryPredicate),
+    WordBoundary(WordBoundaryPredicate),
+    SyllablePosition(SyllablePositionPredicate),
+    Suprasegmental(SuprasegmentalPredicate),
+}
+
+/// A result as it is saved in a cached program, one variant for every kind of result.
+#[derive(Serialize)]
+pub enum ResultRef<'a> {
+    Simple(&'a SimpleResult),
+    SimpleApplication(&'a SimpleApplicationResult),
+    Delete(&'a DeleteResult),
+    Position(&'a PositionResult),
+    Suprasegmental(&'a SuprasegmentalResult),
+}
+
+/// The same as `ResultRef`, but owning the result, for loading it back.
+#[derive(Deserialize)]
+enum ResultData {
+    Simple(SimpleResult),
+    SimpleApplication(SimpleApplicationResult),
+    Delete(DeleteResult),
+    Position(PositionResult),
+    Suprasegmental(SuprasegmentalResult),
+}
+
+impl Serialize for Box<dyn Predicate> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.as_saved().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Box<dyn Predicate> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        Ok(match PredicateData::deserialize(deserializer)? {
+            PredicateData::Multi(v) => Box::new(v),
+            PredicateData::Simple(v) => Box::new(v),
+            PredicateData::PositiveNegative(v) => Box::new(v),
+            PredicateData::SyllableBoundary(v) => Box::new(v),
+            PredicateData::WordBoundary(v) => Box::new(v),
+            PredicateData::SyllablePosition(v) => Box::new(v),
+            PredicateData::Suprasegmental(v) => Box::new(v),
+        })
+    }
+}
+
+impl Serialize for Box<dyn Result> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.as_saved().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Box<dyn Result> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        Ok(match ResultData::deserialize(deserializer)? {
+            ResultData::Simple(v) => Box::new(v),
+            ResultData::SimpleApplication(v) => Box::new(v),
+            ResultData::Delete(v) => Box::new(v),
+            ResultData::Position(v) => Box::new(v),
+            ResultData::Suprasegmental(v) => Box::new(v),
+        })
+    }
+}
+
 impl Predicate for MultiPredicate {
     fn validate(&self, word: &Word, position: usize) -> bool {
         let mut flag: bool = self.req_all;
@@ -38,19 +169,46 @@ impl Predicate for MultiPredicate {
         }
         flag
     }
+
+    fn could_match(&self, letter: &Letter) -> bool {
+        if self.req_all {
+            self.predicate.iter().all(|p| p.could_match(letter))
+        } else {
+            self.predicate.iter().any(|p| p.could_match(letter))
+        }
+    }
+
+    fn as_saved(&self) -> PredicateRef<'_> {
+        PredicateRef::Multi(self)
+    }
+
+    fn share_structure(&mut self, structure: &Rc<SyllableStructure>) {
+        for predicate in &mut self.predicate {
+            predicate.share_structure(structure);
+        }
+    }
 }
 
 impl Predicate for SimplePredicate {
     fn validate(&self, word: &Word, position: usize) -> bool {
-        let letter = word[position];
+        self.could_match(&word[position])
+    }
+
+    fn could_match(&self, letter: &Letter) -> bool {
         (letter.value & self.mask) == self.key
     }
+
+    fn as_saved(&self) -> PredicateRef<'_> {
+        PredicateRef::Simple(self)
+    }
 }
 
 impl Predicate for PositiveNegativePredicate {
     fn validate(&self, word: &Word, position: usize) -> bool {
-        let letter = word[position];
+        self.could_match(&word[position])
+    }
 
+    fn could_match(&self, letter: &Letter) -> bool {
         if (letter.value & self.positive_mask) != self.positive_key {
             return false;
         }
@@ -64,12 +222,105 @@ impl Predicate for PositiveNegativePredicate {
         }
         true
     }
+
+    fn as_saved(&self) -> PredicateRef<'_> {
+        PredicateRef::PositiveNegative(self)
+    }
+}
+
+impl Predicate for SyllableBoundaryPredicate {
+    fn validate(&self, word: &Word, position: usize) -> bool {
+        word.is_syllable_boundary(position)
+    }
+
+    fn as_saved(&self) -> PredicateRef<'_> {
+        PredicateRef::SyllableBoundary(self)
+    }
+}
+
+impl Predicate for WordBoundaryPredicate {
+    fn validate(&self, word: &Word, position: usize) -> bool {
+        word.is_word_boundary(position)
+    }
+
+    fn as_saved(&self) -> PredicateRef<'_> {
+        PredicateRef::WordBoundary(self)
+    }
+}
+
+impl Predicate for SyllablePositionPredicate {
+    fn validate(&self, word: &Word, position: usize) -> bool {
+        if !self.predicate.validate(word, position) {
+            return false;
+        }
+
+        let syllable = word.syllable_at(position);
+        match self.position {
+            SyllablePosition::Initial => syllable.start == 0,
+            SyllablePosition::Final => syllable.end >= word.len(),
+            _ => {
+                let nucleus = match &self.structure {
+                    Some(structure) => structure.find_nucleus(word, syllable),
+                    None => None,
+                };
+                match nucleus {
+                    Some((start, end)) => match self.position {
+                        SyllablePosition::Onset => position < start,
+                        SyllablePosition::Nucleus => start <= position && position < end,
+                        _ => position >= end,
+                    },
+                    None => false,
+                }
+            }
+        }
+    }
+
+    fn could_match(&self, letter: &Letter) -> bool {
+        self.predicate.could_match(letter)
+    }
+
+    fn as_saved(&self) -> PredicateRef<'_> {
+        PredicateRef::SyllablePosition(self)
+    }
+
+    fn share_structure(&mut self, structure: &Rc<SyllableStructure>) {
+        self.predicate.share_structure(structure);
+        self.structure = Some(structure.clone());
+    }
+}
+
+impl Predicate for SuprasegmentalPredicate {
+    fn validate(&self, word: &Word, position: usize) -> bool {
+        if !self.predicate.validate(word, position) {
+            return false;
+        }
+
+        let syllable = word.syllable_at(position);
+        self.stress.is_none_or(|v| syllable.stress == v)
+            && self.tone.is_none_or(|v| syllable.tone == v)
+    }
+
+    fn could_match(&self, letter: &Letter) -> bool {
+        self.predicate.could_match(letter)
+    }
+
+    fn as_saved(&self) -> PredicateRef<'_> {
+        PredicateRef::Suprasegmental(self)
+    }
+
+    fn share_structure(&mut self, structure: &Rc<SyllableStructure>) {
+        self.predicate.share_structure(structure);
+    }
 }
 
 impl Result for SimpleResult {
     fn transform(&self, _input: &Letter) -> Option<Letter> {
         Some(self.letter)
     }
+
+    fn as_saved(&self) -> ResultRef<'_> {
+        ResultRef::Simple(self)
+    }
 }
 
 impl Result for SimpleApplicationResult {
@@ -77,12 +328,63 @@ impl Result for SimpleApplicationResult {
         let value = (input.value & !self.mask) | self.value;
         Some(Letter { value })
     }
+
+    fn as_saved(&self) -> ResultRef<'_> {
+        ResultRef::SimpleApplication(self)
+    }
 }
 
 impl Result for DeleteResult {
     fn transform(&self, _input: &Letter) -> Option<Letter> {
         None
     }
+
+    fn as_saved(&self) -> ResultRef<'_> {
+        ResultRef::Delete(self)
+    }
+}
+
+impl Result for PositionResult {
+    fn transform(&self, input: &Letter) -> Option<Letter> {
+        match &self.result {
+            Some(result) => result.transform(input),
+            None => Some(*input),
+        }
+    }
+
+    fn source(&self) -> Option<usize> {
+        Some(self.position)
+    }
+
+    fn as_saved(&self) -> ResultRef<'_> {
+        ResultRef::Position(self)
+    }
+}
+
+impl Result for SuprasegmentalResult {
+    fn transform(&self, input: &Letter) -> Option<Letter> {
+        match &self.result {
+            Some(result) => result.transform(input),
+            None => Some(*input),
+        }
+    }
+
+    fn source(&self) -> Option<usize> {
+        self.result.as_ref().and_then(|v| v.source())
+    }
+
+    fn transform_syllable(&self, syllable: &mut SyllableDefinition) {
+        if let Some(v) = self.stress {
+            syllable.stress = v;
+        }
+        if let Some(v) = self.tone {
+            syllable.tone = v;
+        }
+    }
+
+    fn as_saved(&self) -> ResultRef<'_> {
+        ResultRef::Suprasegmental(self)
+    }
 }
 
 pub fn create_multi_predicate(
@@ -95,15 +397,15 @@ pub fn create_multi_predicate(
     }
 }
 
-pub fn create_simple_predicate(key: u64, mask: u64) -> SimplePredicate {
+pub fn create_simple_predicate(key: LetterValue, mask: LetterValue) -> SimplePredicate {
     SimplePredicate { key, mask }
 }
 
 pub fn create_positive_negative_predicate(
-    positive_mask: u64,
-    positive_key: u64,
-    negative_masks: Vec<u64>,
-    negative_keys: Vec<u64>,
+    positive_mask: LetterValue,
+    positive_key: LetterValue,
+    negative_masks: Vec<LetterValue>,
+    negative_keys: Vec<LetterValue>,
 ) -> PositiveNegativePredicate {
     if negative_masks.len() != negative_keys.len() {
         panic!("Mismatched number of masks and keys");
@@ -116,14 +418,65 @@ pub fn create_positive_negative_predicate(
     }
 }
 
+pub fn create_syllable_boundary_predicate() -> SyllableBoundaryPredicate {
+    SyllableBoundaryPredicate {}
+}
+
+pub fn create_word_boundary_predicate() -> WordBoundaryPredicate {
+    WordBoundaryPredicate {}
+}
+
+pub fn create_syllable_position_predicate(
+    predicate: Box<dyn Predicate>,
+    position: SyllablePosition,
+    structure: Option<Rc<SyllableStructure>>,
+) -> SyllablePositionPredicate {
+    SyllablePositionPredicate {
+        predicate,
+        position,
+        structure,
+    }
+}
+
+pub fn create_suprasegmental_predicate(
+    predicate: Box<dyn Predicate>,
+    stress: Option<Stress>,
+    tone: Option<u16>,
+) -> SuprasegmentalPredicate {
+    SuprasegmentalPredicate {
+        predicate,
+        stress,
+        tone,
+    }
+}
+
 pub fn create_simple_result(letter: Letter) -> SimpleResult {
     SimpleResult { letter }
 }
 
-pub fn create_simple_application_result(mask: u64, value: u64) -> SimpleApplicationResult {
+pub fn create_simple_application_result(
+    mask: LetterValue,
+    value: LetterValue,
+) -> SimpleApplicationResult {
     SimpleApplicationResult { mask, value }
 }
 
 pub fn create_delete_result() -> DeleteResult {
     DeleteResult {}
 }
+
+pub fn create_position_result(position: usize, result: Option<Box<dyn Result>>) -> PositionResult {
+    PositionResult { position, result }
+}
+
+pub fn create_suprasegmental_result(
+    result: Option<Box<dyn Result>>,
+    stress: Option<Stress>,
+    tone: Option<u16>,
+) -> SuprasegmentalResult {
+    SuprasegmentalResult {
+        result,
+        stress,
+        tone,
+    }
+}
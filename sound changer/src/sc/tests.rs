@@ -1,6 +1,9 @@
 use super::applicator::*;
+use super::cache::*;
 use super::constructor::*;
 use super::data::*;
+use super::formatter::*;
+use super::lint::*;
 use super::rules::*;
 use crate::io::*;
 
@@ -31,8 +34,8 @@ fn test_delete_result() {
 
 #[test]
 fn test_application_result() {
-    let mask = random_u64();
-    let key = random_u64() & mask;
+    let mask = random_value();
+    let key = random_value() & mask;
     let input = random_letter();
     let result_rule = create_simple_application_result(mask, key);
     let result = result_rule.transform(&input).unwrap();
@@ -41,8 +44,8 @@ fn test_application_result() {
 
 #[test]
 fn test_simple_predicate_a() {
-    let mask: u64 = 0xFFFFFFFFFFFFFFFF;
-    let key = random_u64();
+    let mask: LetterValue = LetterValue::MAX;
+    let key = random_value();
     let pos_test = super::data::Letter { value: key };
     let neg_test = loop {
         let neg = random_letter();
@@ -59,8 +62,8 @@ fn test_simple_predicate_a() {
 
 #[test]
 fn test_simple_predicate_b() {
-    let mask: u64 = random_u64();
-    let key = mask & random_u64();
+    let mask: LetterValue = random_value();
+    let key = mask & random_value();
     let pos_test = super::data::Letter { value: key };
     let neg_test = loop {
         let neg = random_letter();
@@ -77,12 +80,12 @@ fn test_simple_predicate_b() {
 
 #[test]
 fn test_multi_predicate_a() {
-    let key = random_u64();
+    let key = random_value();
     let mut tests: Vec<Box<dyn Predicate>> = Vec::new();
 
     let mut i: usize = 0;
-    while i < 64 {
-        let mask: u64 = 1 << i;
+    while i < LETTER_BITS as usize {
+        let mask: LetterValue = 1 << i;
         let current_key = key & mask;
         let predicate = create_simple_predicate(current_key, mask);
         tests.push(Box::new(predicate));
@@ -106,12 +109,12 @@ fn test_multi_predicate_a() {
 
 #[test]
 fn test_multi_predicate_b() {
-    let key = random_u64();
+    let key = random_value();
     let mut tests: Vec<Box<dyn Predicate>> = Vec::new();
 
     let mut i: usize = 0;
-    while i < 64 {
-        let mask: u64 = 1 << i;
+    while i < LETTER_BITS as usize {
+        let mask: LetterValue = 1 << i;
         let current_key = key & mask;
         let predicate = create_simple_predicate(current_key, mask);
         tests.push(Box::new(predicate));
@@ -120,7 +123,7 @@ fn test_multi_predicate_b() {
     }
 
     let fudge = loop {
-        let temp = random_u64();
+        let temp = random_value();
         if temp != 0 {
             break temp;
         }
@@ -137,11 +140,11 @@ fn test_multi_predicate_b() {
 
 #[test]
 fn test_positive_negative_predicate() {
-    let mask: u64 = random_u64();
-    let key = random_u64();
+    let mask: LetterValue = random_value();
+    let key = random_value();
 
     let neg_mask = loop {
-        let temp = random_u64() & !mask;
+        let temp = random_value() & !mask;
         if temp != 0 {
             break temp;
         }
@@ -486,6 +489,40 @@ fn test_missing_node_error() {
     }
 }
 
+#[test]
+fn test_wide_feature_tree() {
+    let mut prog = String::from("feature_def\nswitch type(consonant, vowel) root\n");
+    let mut i: usize = 0;
+    while i < 100 {
+        prog += &format!("feature +f{} vowel\n", i);
+        i += 1;
+    }
+    prog += "end\nsymbols\nsymbol a [vowel]\nsymbol b [vowel +f99]\nend\n";
+    prog += "rules\nrule t\n[+f99] => [-f99 +f0]\n[-f0] => [+f99]\nend\nend";
+    let program = construct(&prog).unwrap();
+    let (_, key) = parse_features(&program, "[vowel +f0]").unwrap();
+    let result = program
+        .apply(from_string(&program, &String::from("ba")).unwrap())
+        .unwrap();
+    assert_eq!(result[0].value, key);
+    assert_eq!(result[1].get_symbol(&program).unwrap(), "b");
+}
+
+#[test]
+fn test_feature_overflow_error() {
+    let mut prog = String::from("feature_def\n");
+    let mut i: usize = 0;
+    while i <= LETTER_BITS as usize {
+        prog += &format!("feature +f{} all\n", i);
+        i += 1;
+    }
+    prog += "end";
+    match construct(&prog) {
+        Ok(_) => panic!(),
+        Err(v) => assert!(v.error_type == ConstructorErrorType::FeatureOverflow),
+    }
+}
+
 #[test]
 fn test_infinite_loop_check() {
     const RULE: &str = "\nrules\nlabel a\njmp a\nend";
@@ -659,6 +696,1152 @@ fn test_captures_d() {
     assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
 }
 
+#[test]
+fn test_metathesis_a() {
+    const INPUT: &str = "askʰa";
+    const CONTENT: &str = "[fricative] [plosive] => %2 %1";
+    const EXPECT: &str = "akʰsa";
+    const RULE: &str = const_format::concatcp!("\nrules\nrule t\n", CONTENT, "\nend\nend");
+    assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
+}
+
+#[test]
+fn test_metathesis_b() {
+    const INPUT: &str = "aska";
+    const CONTENT: &str = "s k => %2 %1[+voice]";
+    const EXPECT: &str = "akza";
+    const RULE: &str = const_format::concatcp!("\nrules\nrule t\n", CONTENT, "\nend\nend");
+    assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
+}
+
+#[test]
+fn test_metathesis_c() {
+    const INPUT: &str = "pat";
+    const CONTENT: &str = "[consonant] [vowel] [consonant] => %3 %1 %2";
+    const EXPECT: &str = "tpa";
+    const RULE: &str = const_format::concatcp!("\nrules\nrule t\n", CONTENT, "\nend\nend");
+    assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
+}
+
+#[test]
+fn test_metathesis_errors() {
+    let defs = create_ipa();
+    assert!(construct(&(defs.clone() + "\nrules\nrule t\ns k => %3 %1\nend\nend")).is_err());
+    assert!(construct(&(defs.clone() + "\nrules\nrule t\ns => %2\nend\nend")).is_err());
+    assert!(construct(&(defs + "\nrules\nrule t\ns k => %0 %1\nend\nend")).is_err());
+}
+
+#[test]
+fn test_rewrite_one_to_many() {
+    const RULE_A: &str = "\nrules\nrule t\ne => i e\nend\nend";
+    assert_eq!(simple_test_helper(RULE_A, "pe.ta"), "pie.ta");
+    assert_eq!(simple_test_helper(RULE_A, "pepe"), "piepie");
+    const RULE_B: &str = "\nrules\nrule t\nt => t [fricative]\nend\nend";
+    assert_eq!(simple_test_helper(RULE_B, "ata"), "atsa");
+    const RULE_C: &str = "\nrules\nrule t rtl\ne => e e\nend\nend";
+    assert_eq!(simple_test_helper(RULE_C, "pe.pe"), "pee.pee");
+}
+
+#[test]
+fn test_rewrite_many_to_one() {
+    const RULE: &str = "\nrules\nrule t\na i => e\nend\nend";
+    assert_eq!(simple_test_helper(RULE, "pa.i"), "pe");
+    assert_eq!(simple_test_helper(RULE, "pai.ta"), "pe.ta");
+    assert_eq!(simple_test_helper(RULE, "ai"), "e");
+}
+
+#[test]
+fn test_rewrite_errors() {
+    let defs = create_ipa();
+    assert!(construct(&(defs.clone() + "\nrules\nrule t\n* => t s\nend\nend")).is_err());
+    assert!(construct(&(defs + "\nrules\nrule t\nt => t$1 s\nend\nend")).is_err());
+}
+
+const CLASSES: &str =
+    "\nclasses\nclass sibilant {s z ʃ ʒ}\nclass hushing {ʃ ʒ ʃ ʒ}\nclass strident {@sibilant f}\nend";
+
+#[test]
+fn test_classes_predicate() {
+    const RULE: &str =
+        const_format::concatcp!(CLASSES, "\nrules\nrule t\n@sibilant => [+voice]\nend\nend");
+    assert_eq!(simple_test_helper(RULE, "asaʃa"), "azaʒa");
+}
+
+#[test]
+fn test_classes_result() {
+    const RULE: &str =
+        const_format::concatcp!(CLASSES, "\nrules\nrule t\n@sibilant => @hushing\nend\nend");
+    assert_eq!(simple_test_helper(RULE, "asza"), "aʃʒa");
+}
+
+#[test]
+fn test_classes_enviorment() {
+    const RULE: &str =
+        const_format::concatcp!(CLASSES, "\nrules\nrule t\na => e / _ @sibilant\nend\nend");
+    assert_eq!(simple_test_helper(RULE, "asata"), "esata");
+}
+
+#[test]
+fn test_classes_nested() {
+    const RULE: &str =
+        const_format::concatcp!(CLASSES, "\nrules\nrule t\n@strident => h\nend\nend");
+    assert_eq!(simple_test_helper(RULE, "fas"), "hah");
+}
+
+#[test]
+fn test_classes_errors() {
+    let defs = create_ipa();
+    match construct(&(defs.clone() + "\nrules\nrule t\n@nothing => h\nend\nend")) {
+        Ok(_) => panic!(),
+        Err(v) => assert!(v.error_type == ConstructorErrorType::MissingClass),
+    }
+    assert!(construct(&(defs.clone() + "\nclasses\nclass a {@a s}\nend")).is_err());
+    assert!(construct(&(defs + "\nclasses\nclass a {s}\nclass a {z}\nend")).is_err());
+}
+
+#[test]
+fn test_feature_variables_place_assimilation() {
+    const RULE: &str = "\nrules\nrule t\nn => [αplace] / _ [αplace plosive]\nend\nend";
+    assert_eq!(simple_test_helper(RULE, "anpa"), "ampa");
+    assert_eq!(simple_test_helper(RULE, "anka"), "aŋka");
+    assert_eq!(simple_test_helper(RULE, "anta"), "anta");
+}
+
+#[test]
+fn test_feature_variables_voicing_agreement() {
+    const RULE: &str = "\nrules\nrule t\n[plosive] => [αvoice] / _ [αvoice consonant]\nend\nend";
+    assert_eq!(simple_test_helper(RULE, "akda"), "agda");
+    assert_eq!(simple_test_helper(RULE, "agta"), "akta");
+}
+
+#[test]
+fn test_feature_variables_enviorment_agreement() {
+    const RULE: &str = "\nrules\nrule t\na => e / [αround] _ [αround]\nend\nend";
+    assert_eq!(simple_test_helper(RULE, "oau"), "oeu");
+    assert_eq!(simple_test_helper(RULE, "oai"), "oai");
+}
+
+#[test]
+fn test_feature_variables_negated() {
+    const RULE: &str = "\nrules\nrule t\n[plosive] => [-αvoice] / _ [αvoice plosive]\nend\nend";
+    assert_eq!(simple_test_helper(RULE, "abda"), "apda");
+}
+
+#[test]
+fn test_feature_variables_flags() {
+    const CONTENT: &str = "n => [αplace] / _ [αplace plosive]\nend\nend";
+    const FIRST: &str = const_format::concatcp!("\nrules\nrule t first\n", CONTENT);
+    assert_eq!(simple_test_helper(FIRST, "anpanka"), "ampanka");
+    assert_eq!(simple_test_helper(FIRST, "ankanpa"), "aŋkanpa");
+    const LAST: &str = const_format::concatcp!("\nrules\nrule t last\n", CONTENT);
+    assert_eq!(simple_test_helper(LAST, "anpanka"), "anpaŋka");
+}
+
+#[test]
+fn test_feature_variables_no_feeding() {
+    //The n is matched against the ŋ after it before the ŋ becomes m, as it would be for one line
+    const RULE: &str = "\nrules\nrule t\n[nasal] => [αplace] / _ [αplace consonant]\nend\nend";
+    assert_eq!(simple_test_helper(RULE, "anŋpa"), "aŋmpa");
+}
+
+#[test]
+fn test_feature_variables_errors() {
+    let defs = create_ipa();
+    let check = |content: &str| {
+        construct(&format!("{}\nrules\nrule t\n{}\nend\nend", defs, content)).is_err()
+    };
+    assert!(check("n => [αfoo] / _ [αfoo]"));
+    assert!(check("n => [αplace] / _ [αvoice]"));
+    assert!(check("n => [-αplace] / _ [αplace]"));
+}
+
+#[test]
+fn test_nonlocal_enviorment_harmony() {
+    const RULE: &str = "\nrules\nrule t\nu => y / [front]~[consonant] _\nend\nend";
+    assert_eq!(simple_test_helper(RULE, "etuku"), "etyky");
+    assert_eq!(simple_test_helper(RULE, "eltu"), "elty");
+    assert_eq!(simple_test_helper(RULE, "otuku"), "otuku");
+    assert_eq!(simple_test_helper(RULE, "tu"), "tu");
+}
+
+#[test]
+fn test_nonlocal_enviorment_blocking() {
+    const RULE: &str = "\nclasses\nclass neutral {[consonant] i}\nend\nrules\nrule t\nu => y / e~@neutral _\nend\nend";
+    assert_eq!(simple_test_helper(RULE, "etiku"), "etiky");
+    assert_eq!(simple_test_helper(RULE, "etoku"), "etoku");
+}
+
+#[test]
+fn test_nonlocal_enviorment_post() {
+    const RULE: &str = "\nrules\nrule t\na => e / _ i~[consonant]\nend\nend";
+    assert_eq!(simple_test_helper(RULE, "arki"), "erki");
+    assert_eq!(simple_test_helper(RULE, "ai"), "ei");
+    assert_eq!(simple_test_helper(RULE, "arko"), "arko");
+}
+
+#[test]
+fn test_multiple_enviorments() {
+    const RULE: &str = "\nrules\nrule t\np => f / [vowel] _ [vowel], _ $ unless s _\nend\nend";
+    assert_eq!(simple_test_helper(RULE, "apa"), "afa");
+    assert_eq!(simple_test_helper(RULE, "ap"), "af");
+    assert_eq!(simple_test_helper(RULE, "asp"), "asp");
+    assert_eq!(simple_test_helper(RULE, "aspa"), "aspa");
+    assert_eq!(simple_test_helper(RULE, "pa"), "pa");
+}
+
+#[test]
+fn test_enviorment_exceptions_only() {
+    const RULE: &str = "\nrules\nrule t\np => f / unless s _, _ $\nend\nend";
+    assert_eq!(simple_test_helper(RULE, "apspa"), "afspa");
+    assert_eq!(simple_test_helper(RULE, "pap"), "fap");
+}
+
+#[test]
+fn test_multiple_enviorments_errors() {
+    let defs = create_ipa();
+    let check = |content: &str| {
+        construct(&format!("{}\nrules\nrule t\n{}\nend\nend", defs, content)).is_err()
+    };
+    assert!(check("p => f / a _ unless s _ unless _ s"));
+    assert!(check("p => f / a _ unless"));
+    assert!(check("p => f / a _, b"));
+    assert!(check("p => f / a _,"));
+    assert!(check("p => f / a _, , b _"));
+    assert!(check("p => f / , a _"));
+}
+
+#[test]
+fn test_lexical_exceptions() {
+    const RULE: &str = "\nrules\nrule lenition\np => f\nend\nrule raising\na => e\nend\nend";
+    let prog: Program = construct(&(create_ipa() + RULE)).unwrap();
+    let apply = |exceptions: &[&str]| {
+        let word = from_string(&prog, &String::from("pap")).unwrap();
+        to_string(&prog, prog.apply_with_exceptions(word, exceptions).unwrap()).unwrap()
+    };
+    assert_eq!(apply(&[]), "fef");
+    assert_eq!(apply(&["lenition"]), "pep");
+    assert_eq!(apply(&["lenition", "raising"]), "pap");
+    let word = from_string(&prog, &String::from("pap")).unwrap();
+    match prog.apply_with_exceptions(word, &["unknown"]) {
+        Ok(_) => panic!(),
+        Err(v) => assert!(matches!(v, ApplicationError::InternalError(_))),
+    }
+}
+
+#[test]
+fn test_rule_flags_sporadic_extremes() {
+    const NEVER: &str = "\nrules\nrule t sporadic 0%\np => f\nend\nend";
+    const ALWAYS: &str = "\nrules\nrule t sporadic 100%\np => f\nend\nend";
+    assert_eq!(simple_test_helper(NEVER, "papapa"), "papapa");
+    assert_eq!(simple_test_helper(ALWAYS, "papapa"), "fafafa");
+}
+
+#[test]
+fn test_rule_flags_sporadic_sites() {
+    const RULE: &str = "\nrules\nrule t sporadic 50%\np => f\nend\nend";
+    const INPUT: &str = "papapapapapapapapapapapapapapapapapapapa";
+    let result = simple_test_helper(RULE, INPUT);
+    assert_eq!(result, simple_test_helper(RULE, INPUT));
+    assert!(result.contains('p'));
+    assert!(result.contains('f'));
+}
+
+#[test]
+fn test_rule_flags_diffuse() {
+    const RULE: &str = "\nrules\nrule t diffuse 50%\np => f\nend\nend";
+    let vowels = ["a", "e", "i", "o", "u"];
+    let mut changed = 0;
+    for a in vowels {
+        for b in vowels {
+            let input = format!("p{}p{}", a, b);
+            let result = simple_test_helper(RULE, &input);
+            assert_eq!(result, simple_test_helper(RULE, &input));
+            if result != input {
+                //Every site in a word changes together
+                assert_eq!(result, format!("f{}f{}", a, b));
+                changed += 1;
+            }
+        }
+    }
+    assert!(changed > 0 && changed < 25);
+}
+
+#[test]
+fn test_rule_flags_chance_phrase() {
+    const RULES: &str =
+        "\nrules\nrule t diffuse 50%\np => f\nend\nrule u sporadic 50%\nt => d\nend\nend";
+    let words: Vec<String> = ["a", "e", "i", "o", "u"]
+        .iter()
+        .flat_map(|a| ["a", "e", "i", "o", "u"].map(|b| format!("p{}t{}tapa", a, b)))
+        .collect();
+    //Each word of a phrase comes out the same as it does alone
+    let alone: Vec<String> = words.iter().map(|v| simple_test_helper(RULES, v)).collect();
+    assert_eq!(simple_test_helper(RULES, &words.join(" ")), alone.join(" "));
+    assert!(alone.iter().any(|v| v.starts_with('f')));
+    assert!(alone.iter().any(|v| v.starts_with('p')));
+}
+
+#[test]
+fn test_rule_flags_chance_feature_layout() {
+    const RULES: &str =
+        "\nrules\nrule t sporadic 50%\np => f\nend\nrule u diffuse 50%\nt => d\nend\nend";
+    let apply = |defs: &str| -> Vec<String> {
+        let program = construct(&(String::from(defs) + RULES)).unwrap();
+        ["papapapapapa", "tapa", "tepe", "tipi", "topo", "tupu"]
+            .iter()
+            .map(|v| {
+                let word = from_string(&program, &String::from(*v)).unwrap();
+                to_string(&program, program.apply(word).unwrap()).unwrap()
+            })
+            .collect()
+    };
+    //An unrelated feature at the start moves the bits of every other feature
+    let defs = create_ipa();
+    let shifted = defs.replacen("feature_def\n", "feature_def\n    feature +extra all\n", 1);
+    assert_ne!(defs, shifted);
+    assert_eq!(apply(&defs), apply(&shifted));
+}
+
+#[test]
+fn test_rule_flags_sporadic_errors() {
+    let defs = create_ipa();
+    let check = |header: &str| {
+        construct(&format!("{}\nrules\n{}\np => f\nend\nend", defs, header)).is_err()
+    };
+    assert!(check("rule t sporadic"));
+    assert!(check("rule t sporadic 101%"));
+    assert!(check("rule t sporadic half"));
+    assert!(check("rule t sporadic 10% diffuse 10%"));
+    assert!(!check("rule t diffuse 10% rtl"));
+}
+
+#[test]
+fn test_include() {
+    const PATH: &str = "test-data/include-test.lsc";
+    let contents = load_from_file(&String::from(PATH), false).unwrap();
+    let prog = construct_with_path(&contents, PATH).unwrap();
+    let word = from_string(&prog, &String::from("apa")).unwrap();
+    assert_eq!(to_string(&prog, prog.apply(word).unwrap()).unwrap(), "afa");
+}
+
+#[test]
+fn test_include_error_lines() {
+    let construct_err = |input: &str| match construct_with_path(input, "test-data/main.lsc") {
+        Ok(_) => panic!(),
+        Err(v) => v,
+    };
+    let main = "include \"full-ipa.lsc\"\nrules\nrule t\np => [nonsense]\nend\nend";
+    let error = construct_err(main);
+    assert_eq!(
+        error.line_number_user_program,
+        LineNumberInformation::Raw(4)
+    );
+    assert_eq!(error.file, None);
+
+    let error = construct_err("\ninclude \"include-broken.lsc\"");
+    assert_eq!(
+        error.line_number_user_program,
+        LineNumberInformation::Raw(2)
+    );
+    assert!(error.file.unwrap().ends_with("include-broken.lsc"));
+}
+
+#[test]
+fn test_include_errors() {
+    let construct_err = |input: &str| match construct_with_path(input, "test-data/main.lsc") {
+        Ok(_) => panic!(),
+        Err(v) => v,
+    };
+    let error = construct_err("include \"missing.lsc\"");
+    assert_eq!(error.error_type, ConstructorErrorType::MissingInclude);
+    construct_err("include missing.lsc");
+    construct_err("include \"include-self.lsc\"");
+
+    //A program that isn't in a file has nothing to find its includes relative to, even when
+    //the working directory has the file
+    let errors = match construct_detached("include \"test-data/full-ipa.lsc\"") {
+        Ok(_) => panic!(),
+        Err(v) => v,
+    };
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        errors[0].error_type,
+        ConstructorErrorType::MalformedDefinition
+    );
+    assert!(construct_detached(&create_ipa()).is_ok());
+}
+
+const SPELLING: &str = "\nderomanizer\nsh => ʃ\nc => k\ny => j / $ _\ny => i\nh => * / _ $\nend\nromanizer\nʃ => sh\nk => c\nj => y\nend";
+
+#[test]
+fn test_deromanizer() {
+    let prog: Program = construct(&(create_ipa() + SPELLING)).unwrap();
+    assert_eq!(prog.deromanize("shyc"), "ʃik");
+    assert_eq!(prog.deromanize("yay yah"), "jai ja");
+    assert_eq!(prog.romanize("ʃajk"), "shayc");
+}
+
+#[test]
+fn test_romanizer_round_trip() {
+    const RULE: &str = const_format::concatcp!(SPELLING, "\nrules\nrule t\ns => ʃ / _ i\nend\nend");
+    let prog: Program = construct(&(create_ipa() + RULE)).unwrap();
+    let word = from_string(&prog, &prog.deromanize("casy")).unwrap();
+    let result = to_string(&prog, prog.apply(word).unwrap()).unwrap();
+    assert_eq!(prog.romanize(&result), "cashi");
+}
+
+#[test]
+fn test_romanize_word_syllables() {
+    const RULE: &str = const_format::concatcp!(
+        SPELLING,
+        SYLLABLES,
+        "\nrules\nrule t\ns => ʃ / _ i\nend\nrule s\n[vowel] => :stressed / $ [consonant] _\nend\nend"
+    );
+    let prog: Program = construct(&(create_ipa() + RULE)).unwrap();
+    let word = from_string(&prog, &prog.deromanize("casy")).unwrap();
+    let result = prog.apply(word).unwrap();
+    assert_eq!(to_string(&prog, result.clone()).unwrap(), "ˈka.ʃi");
+    assert_eq!(prog.romanize_word(result).unwrap(), "cashi");
+
+    //Without a romanizer the marks are kept
+    let prog: Program = construct(&(create_ipa() + SYLLABLES)).unwrap();
+    let word = from_string(&prog, &String::from("ˈka.si")).unwrap();
+    assert_eq!(prog.romanize_word(word).unwrap(), "ˈka.si");
+}
+
+#[test]
+fn test_read_word_spelling() {
+    const RULE: &str = const_format::concatcp!(SPELLING, "\nrules\nrule t\ns => ʃ / _ i\nend\nend");
+    let prog: Program = construct(&(create_ipa() + RULE)).unwrap();
+
+    //Words are only spelled when asked, so symbols the deromanizer would change are kept
+    let word = prog.read_word("sy", false).unwrap();
+    assert_eq!(
+        prog.write_word(prog.apply(word).unwrap(), false).unwrap(),
+        "sy"
+    );
+    let word = prog.read_word("kasi", false).unwrap();
+    assert_eq!(
+        prog.write_word(prog.apply(word).unwrap(), false).unwrap(),
+        "kaʃi"
+    );
+
+    let word = prog.read_word("sy", true).unwrap();
+    assert_eq!(
+        prog.write_word(prog.apply(word).unwrap(), true).unwrap(),
+        "shi"
+    );
+    let word = prog.read_word("casy", true).unwrap();
+    assert_eq!(
+        prog.write_word(prog.apply(word).unwrap(), true).unwrap(),
+        "cashi"
+    );
+}
+
+#[test]
+fn test_spelling_rule_errors() {
+    let defs = create_ipa();
+    let check =
+        |content: &str| construct(&format!("{}\nromanizer\n{}\nend", defs, content)).is_err();
+    assert!(check("sh ʃ"));
+    assert!(check("=> ʃ"));
+    assert!(check("ʃ => sh / _ _"));
+    assert!(check("ʃ => sh / a $ _"));
+    assert!(construct(&(defs + "\nderomanizer\nsh => ʃ")).is_err());
+}
+
+#[test]
+fn test_stages() {
+    const RULE: &str = "\nrules\nrule a\np => f\nend\nstage old\nrule b\na => e\nend\nstage middle\nrule c\nf => h\nend\nstage modern\nend";
+    let prog: Program = construct(&(create_ipa() + RULE)).unwrap();
+    let stages = prog
+        .apply_with_stages(from_string(&prog, &String::from("pa")).unwrap())
+        .unwrap();
+    let stages: Vec<(String, String)> = stages
+        .into_iter()
+        .map(|(name, word)| (name, to_string(&prog, word).unwrap()))
+        .collect();
+    assert_eq!(
+        stages,
+        vec![
+            (String::from("old"), String::from("fa")),
+            (String::from("middle"), String::from("fe")),
+            (String::from("modern"), String::from("he")),
+        ]
+    );
+    assert_eq!(simple_test_helper(RULE, "pa"), "he");
+}
+
+#[test]
+fn test_stages_errors() {
+    let defs = create_ipa();
+    assert!(construct(&(defs.clone() + "\nrules\nstage\nend")).is_err());
+    assert!(construct(&(defs + "\nrules\nstage old\nstage old\nend")).is_err());
+}
+
+#[test]
+fn test_derivation_trace() {
+    const PROGRAM: &str = "include \"full-ipa.lsc\"\nrules\nrule a\np => f\nend\nsub s\nrule b\na => e\nend\nrule c\no => u\nend\nend\ncall s\nrule d\ni => y\nend\nend";
+    let prog = construct_with_path(PROGRAM, "test-data/main.lsc").unwrap();
+    let (word, steps) = prog
+        .apply_traced(from_string(&prog, &String::from("papo")).unwrap())
+        .unwrap();
+    assert_eq!(to_string(&prog, word).unwrap(), "fefu");
+    let steps: Vec<(String, u32, String)> = steps
+        .into_iter()
+        .map(|v| (v.rule, v.line_number, v.result))
+        .collect();
+    assert_eq!(
+        steps,
+        vec![
+            (String::from("a"), 3, String::from("fafo")),
+            (String::from("b"), 7, String::from("fefo")),
+            (String::from("c"), 10, String::from("fefu")),
+        ]
+    );
+}
+
+#[test]
+fn test_apply_reverse() {
+    const RULE: &str =
+        "\nrules\nrule a\np => f\nend\nrule b\na => e\nend\nrule c\nh => *\nend\nend";
+    let prog: Program = construct(&(create_ipa() + RULE)).unwrap();
+    let target = from_string(&prog, &String::from("fe")).unwrap();
+    let candidates = prog.apply_reverse(&target, 200);
+    let candidates: Vec<String> = candidates
+        .into_iter()
+        .map(|v| {
+            assert_eq!(
+                to_string(&prog, prog.apply(v.clone()).unwrap()).unwrap(),
+                "fe"
+            );
+            to_string(&prog, v).unwrap()
+        })
+        .collect();
+    assert_eq!(candidates[0], "fe");
+    for expect in ["pe", "fa", "pa", "hfe", "pha"] {
+        assert!(candidates.contains(&String::from(expect)), "{}", expect);
+    }
+
+    assert_eq!(prog.apply_reverse(&target, 3).len(), 3);
+    assert!(prog.apply_reverse(&target, 0).is_empty());
+
+    //Only letters the rules can change are tried, so t and k never are
+    let alphabet: Vec<String> = prog
+        .reverse_alphabet()
+        .iter()
+        .map(|v| v.get_symbol(&prog).unwrap())
+        .collect();
+    for expect in ["p", "a", "h"] {
+        assert!(alphabet.contains(&String::from(expect)), "{}", expect);
+    }
+    assert!(!alphabet.contains(&String::from("t")));
+    assert!(!alphabet.contains(&String::from("k")));
+}
+
+#[test]
+fn test_phrase_round_trip() {
+    let prog: Program = construct(&create_ipa()).unwrap();
+    let word = from_string(&prog, &String::from(" pa.ta  ka ")).unwrap();
+    assert_eq!(word.word_breaks, vec![4]);
+    assert_eq!(to_string(&prog, word).unwrap(), "pa.ta ka");
+}
+
+#[test]
+fn test_phrase_word_boundary() {
+    const RULE: &str = "\nrules\nrule t\na => e / _ $\nend\nend";
+    assert_eq!(simple_test_helper(RULE, "pa ta"), "pe te");
+
+    //A boundary between words can only be matched by a sandhi rule
+    let defs = create_ipa();
+    let error = match construct(&(defs.clone() + "\nrules\nrule t\np => f / a $ _\nend\nend")) {
+        Ok(_) => panic!(),
+        Err(v) => v,
+    };
+    assert_eq!(error.error_type, ConstructorErrorType::MalformedDefinition);
+    assert!(construct(&(defs.clone() + "\nrules\nrule t\np => f / _ a, a $ _\nend\nend")).is_err());
+    assert!(construct(&(defs + "\nrules\nrule t sandhi\np => f / a $ _\nend\nend")).is_ok());
+}
+
+#[test]
+fn test_phrase_boundary() {
+    const RULE: &str =
+        "\nrules\nrule t\na => e / _ $$\nend\nrule u\no => u / $$ [consonant] _\nend\nend";
+    assert_eq!(simple_test_helper(RULE, "po ta po ta"), "pu ta po te");
+}
+
+#[test]
+fn test_phrase_sandhi() {
+    const RULE: &str = "\nrules\nrule t\na => e / _ t\nend\nend";
+    assert_eq!(simple_test_helper(RULE, "pa ta"), "pa ta");
+    const SANDHI: &str = "\nrules\nrule t sandhi\na => e / _ t\nend\nend";
+    assert_eq!(simple_test_helper(SANDHI, "pa ta"), "pe ta");
+    const MEDIAL: &str = "\nrules\nrule t sandhi\na => * / _ $ [vowel]\nend\nend";
+    assert_eq!(simple_test_helper(MEDIAL, "pa ata pata"), "p ata pata");
+    const INSERT: &str = "\nrules\nrule t sandhi\n* => n / [vowel] _ $ [vowel]\nend\nend";
+    assert_eq!(simple_test_helper(INSERT, "pa ata"), "pan ata");
+    const LIAISON: &str = "\nrules\nrule t sandhi\n* => z / [vowel] $ _ [vowel]\nend\nend";
+    assert_eq!(simple_test_helper(LIAISON, "pa ata"), "pa zata");
+}
+
+#[test]
+fn test_construct_recovering() {
+    const RULES: &str = "\nrules\nrule a\np => [+nonexistent]\nend\n  frobnicate x\nrule b\nt => d\nend\nrule c\nrule d\nend\nend\nend";
+    let offset = create_ipa().split('\n').count() as u32;
+    let errors = match construct_recovering(&(create_ipa() + RULES), "") {
+        Ok(_) => panic!(),
+        Err(v) => v,
+    };
+    let errors: Vec<_> = errors
+        .into_iter()
+        .map(|e| match e.line_number_user_program {
+            LineNumberInformation::Raw(v) => (v - offset, e.column_span, e.error_type),
+            _ => panic!(),
+        })
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            (3, Some((6, 18)), ConstructorErrorType::MissingFeature),
+            (5, Some((2, 12)), ConstructorErrorType::UnknownCommandError),
+            (10, Some((0, 6)), ConstructorErrorType::MalformedDefinition),
+        ]
+    );
+
+    //Stopping at the first error gives the same first error
+    let error = match construct(&(create_ipa() + RULES)) {
+        Ok(_) => panic!(),
+        Err(v) => v,
+    };
+    assert_eq!(error.error_type, ConstructorErrorType::MissingFeature);
+    assert!(
+        construct_recovering(&(create_ipa() + "\nrules\nrule b\nt => d\nend\nend"), "").is_ok()
+    );
+}
+
+#[test]
+fn test_lint() {
+    const RULES: &str = "\nsymbols\n    symbol P [-voice bilabial plosive]\nend\nrules\nrule a\n[+nasal-release vowel] => e\nend\nrule b\np => [+lateral]\nend\nlabel unused\nsub never\nrule d\nt => d\nend\nend\nsub used\nrule e\nk => g\nend\nend\ncall used\nrule f\n* => [+nasal-release vowel] / t _\nend\nend";
+    let offset = create_ipa().split('\n').count() as u32;
+    let program = construct(&(create_ipa() + RULES)).expect("Program should construct");
+    let warnings: Vec<_> = program
+        .lint()
+        .into_iter()
+        .map(|w| (w.lint_type, w.line_number.map(|v| v - offset)))
+        .collect();
+    assert_eq!(
+        warnings,
+        vec![
+            (LintType::ShadowedSymbol, None),
+            (LintType::UnmatchablePredicate, Some(5)),
+            (LintType::UnwritableResult, Some(8)),
+            (LintType::UnwritableResult, Some(23)),
+            (LintType::UnusedLabel, None),
+            (LintType::UncalledSubroutine, Some(13)),
+        ]
+    );
+
+    assert!(construct(&create_ipa()).unwrap().lint().is_empty());
+}
+
+#[test]
+fn test_lint_symbols() {
+    const SYMBOLS: &str = "\nsymbols\n    symbol ts [-voice alveolar affricate]\n    symbol ˈe [close-mid front -round +long]\nend";
+    let program = construct(&(create_ipa() + SYMBOLS)).expect("Program should construct");
+    let warnings: Vec<_> = program.lint().into_iter().map(|w| w.message).collect();
+    assert_eq!(
+        warnings,
+        vec![
+            "\"ˈe\" is read back as \"e\"",
+            "\"t\" followed by \"s\" is read as \"ts\"",
+        ]
+    );
+}
+
+#[test]
+fn test_from_string_longest_symbol() {
+    const SYMBOLS: &str = "\nsymbols\n    symbol ts [-voice alveolar affricate]\nend";
+    let program = construct(&(create_ipa() + SYMBOLS)).expect("Program should construct");
+    let ts = from_string(&program, &String::from("ts")).unwrap().letters[0];
+    //Every letter is read as the longest symbol it starts with. This used to be true only of
+    //the first letter: after a shorter symbol matched, the search carried on through the
+    //remaining shorter symbols, so "əts" was read as "ə" "t" "s"
+    for input in ["ts", "əts", "atsa", "ətsts"] {
+        let word = from_string(&program, &String::from(input)).unwrap();
+        let count = input.matches("ts").count();
+        assert_eq!(word.letters.iter().filter(|v| **v == ts).count(), count);
+        assert_eq!(to_string(&program, word).unwrap(), input);
+    }
+}
+
+#[test]
+fn test_format_program() {
+    const INPUT: &str = "\n\nrules\n  rule  a   ltr # comment\n[vowel]  => [+long]\n  t d => d t   / _ [vowel]\n\n\n# note\n    p => f //   $ _\n  end\nsub s\nrule b\nk=>g\nend\nend\nend\n\n";
+    const EXPECT: &str = "rules\n    rule a ltr # comment\n        [vowel] => [+long]\n        t d     => d t / _ [vowel]\n\n        # note\n        p => f // $ _\n    end\n    sub s\n        rule b\n            k => g\n        end\n    end\nend\n";
+    assert_eq!(format_program(INPUT), EXPECT);
+    assert_eq!(format_program(EXPECT), EXPECT);
+
+    //Formatting doesn't change what a program does
+    for (path, words) in [
+        ("test-data/int-test-1.lsc", "test-data/int-test-1.words.txt"),
+        ("test-data/int-test-2.lsc", "test-data/int-test-2.words.txt"),
+    ] {
+        let rules = load_from_file(&String::from(path), false).unwrap();
+        let formatted = format_program(&rules);
+        assert_eq!(format_program(&formatted), formatted);
+        let program = construct(&format!("{0}\n{1}", create_ipa(), formatted)).unwrap();
+        let words = load_from_file(&String::from(words), false).unwrap();
+        for l in words.split('\n') {
+            let parts: Vec<&str> = l.split(':').collect();
+            let word = from_string(&program, &String::from(parts[0].trim())).unwrap();
+            let result = to_string(&program, program.apply(word).unwrap());
+            assert_eq!(result.unwrap(), parts[1].trim());
+        }
+    }
+}
+
+#[test]
+fn test_construct_cached() {
+    let path = std::env::temp_dir()
+        .join(format!("lexica-cache-test-{}.lsc", std::process::id()))
+        .display()
+        .to_string();
+    let rules = load_from_file(&String::from("test-data/int-test-1.lsc"), false).unwrap();
+    let source = format!("{0}\n{1}", create_ipa(), rules);
+    std::fs::write(&path, &source).unwrap();
+
+    assert!(load_cached(&source, &path).is_none());
+    construct_cached(&source, &path).unwrap();
+    let program = load_cached(&source, &path).expect("Program should be cached");
+    let words = load_from_file(&String::from("test-data/int-test-1.words.txt"), false).unwrap();
+    for l in words.split('\n') {
+        let parts: Vec<&str> = l.split(':').collect();
+        let word = from_string(&program, &String::from(parts[0].trim())).unwrap();
+        let result = to_string(&program, program.apply(word).unwrap());
+        assert_eq!(result.unwrap(), parts[1].trim());
+    }
+
+    //A changed source is constructed again instead of using the old cache
+    let changed = source.clone() + "\nrules\nrule t\nt => d\nend\nend";
+    assert!(load_cached(&changed, &path).is_none());
+    let program = construct_cached(&changed, &path).unwrap();
+    let word = from_string(&program, &String::from("ta")).unwrap();
+    assert_eq!(
+        to_string(&program, program.apply(word).unwrap()).unwrap(),
+        "da"
+    );
+    assert!(load_cached(&source, &path).is_none());
+
+    //A cache without the right header is never deserialized
+    let cache = std::fs::read(get_cache_path(&path)).unwrap();
+    assert!(cache.starts_with(b"LEXICA-SC-CACHE\0"));
+    let mut wrong_version = cache.clone();
+    wrong_version[16] ^= 0xff;
+    std::fs::write(get_cache_path(&path), wrong_version).unwrap();
+    assert!(load_cached(&changed, &path).is_none());
+    std::fs::write(get_cache_path(&path), &cache[16..]).unwrap();
+    assert!(load_cached(&changed, &path).is_none());
+    construct_cached(&changed, &path).unwrap();
+    assert!(load_cached(&changed, &path).is_some());
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(get_cache_path(&path)).unwrap();
+}
+
+#[test]
+fn test_construct_cached_syllable_structure() {
+    const PROG: &str = "\nsyllables\nonset [consonant]<0:1>\nnucleus [vowel]\ncoda [consonant]<0:1>\nend\nrules\nrule t\n[+voice]:coda => [-voice] / :nucleus _\nend\nend";
+    let path = std::env::temp_dir()
+        .join(format!("lexica-cache-syllables-{}.lsc", std::process::id()))
+        .display()
+        .to_string();
+    let source = format!("{0}\n{1}", create_ipa(), PROG);
+    std::fs::write(&path, &source).unwrap();
+
+    construct_cached(&source, &path).unwrap();
+    let program = load_cached(&source, &path).expect("Program should be cached");
+    let word = from_string(&program, &String::from("badba")).unwrap();
+    assert_eq!(
+        to_string(&program, program.apply(word).unwrap()).unwrap(),
+        "bat.ba"
+    );
+
+    //The loaded predicates use the program's syllable structure, not copies of it
+    let structure = program.syllable_structure.as_ref().unwrap();
+    let byte = match &program.rules[0] {
+        Rule::TransformationRule { bytes, .. } => &bytes[0],
+        _ => panic!(),
+    };
+    let predicates = [
+        &byte.transformations[0].predicate[0],
+        &byte.enviorment.ante[0].predicate,
+    ];
+    for predicate in predicates {
+        match predicate.as_saved() {
+            PredicateRef::SyllablePosition(v) => {
+                assert!(std::rc::Rc::ptr_eq(
+                    v.structure.as_ref().unwrap(),
+                    structure
+                ))
+            }
+            _ => panic!(),
+        }
+    }
+
+    //A cache with a length past the size limit is rejected before anything is allocated
+    let cache = std::fs::read(get_cache_path(&path)).unwrap();
+    let header_len = 16 + 4 + env!("CARGO_PKG_VERSION").len() + 1 + 8;
+    let mut oversized = cache[..header_len].to_vec();
+    oversized.push(253);
+    oversized.extend_from_slice(&u64::MAX.to_le_bytes());
+    std::fs::write(get_cache_path(&path), oversized).unwrap();
+    assert!(load_cached(&source, &path).is_none());
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(get_cache_path(&path)).unwrap();
+}
+
+#[test]
+fn test_rule_flags_ltr() {
+    const INPUT: &str = "aaaː";
+    const CONTENT: &str = "[vowel] => [+long] / _ [+long]";
+    const EXPECT: &str = "aaːaː";
+    const RULE: &str = const_format::concatcp!("\nrules\nrule t\n", CONTENT, "\nend\nend");
+    assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
+}
+
+#[test]
+fn test_rule_flags_rtl() {
+    const INPUT: &str = "aaaː";
+    const CONTENT: &str = "[vowel] => [+long] / _ [+long]";
+    const EXPECT: &str = "aːaːaː";
+    const RULE: &str = const_format::concatcp!("\nrules\nrule t rtl\n", CONTENT, "\nend\nend");
+    assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
+}
+
+#[test]
+fn test_rule_flags_rtl_multi() {
+    const INPUT: &str = "ababab";
+    const CONTENT: &str = "a b => * b / _ a";
+    const EXPECT: &str = "abbab";
+    const RULE: &str = const_format::concatcp!("\nrules\nrule t rtl\n", CONTENT, "\nend\nend");
+    assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
+}
+
+#[test]
+fn test_rule_flags_rtl_insertion() {
+    const INPUT: &str = "tata";
+    const CONTENT: &str = "* => e / t _";
+    const EXPECT: &str = "teatea";
+    const RULE: &str = const_format::concatcp!("\nrules\nrule t rtl\n", CONTENT, "\nend\nend");
+    assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
+}
+
+#[test]
+fn test_rule_flags_first() {
+    const INPUT: &str = "tata";
+    const CONTENT: &str = "a => e";
+    const EXPECT: &str = "teta";
+    const RULE: &str = const_format::concatcp!("\nrules\nrule t first\n", CONTENT, "\nend\nend");
+    assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
+}
+
+#[test]
+fn test_rule_flags_first_multiple_lines() {
+    const CONTENT: &str = "n => m / _ p\nn => ŋ / _ k";
+    const RULE: &str = const_format::concatcp!("\nrules\nrule t first\n", CONTENT, "\nend\nend");
+    assert_eq!(simple_test_helper(RULE, "anpanka"), "ampanka");
+    //The first place any line matches is used, even when a later line matches it
+    assert_eq!(simple_test_helper(RULE, "ankanpa"), "aŋkanpa");
+    const LAST: &str = const_format::concatcp!("\nrules\nrule t last\n", CONTENT, "\nend\nend");
+    assert_eq!(simple_test_helper(LAST, "anpanka"), "anpaŋka");
+}
+
+#[test]
+fn test_rule_flags_mod_multiple_lines() {
+    //Only the first line changes the word, and the jump still sees it
+    const RULE: &str =
+        "\nrules\nrule t\na => e\nq => k\nend\njmp skip mod\nrule u\nt => d\nend\nlabel skip\nend";
+    assert_eq!(simple_test_helper(RULE, "ta"), "te");
+}
+
+#[test]
+fn test_rule_flags_last() {
+    const INPUT: &str = "tata";
+    const CONTENT: &str = "a => e";
+    const EXPECT: &str = "tate";
+    const RULE: &str = const_format::concatcp!("\nrules\nrule t last\n", CONTENT, "\nend\nend");
+    assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
+}
+
+#[test]
+fn test_rule_flags_rtl_last() {
+    const INPUT: &str = "tata";
+    const CONTENT: &str = "a => e";
+    const EXPECT: &str = "teta";
+    const RULE: &str = const_format::concatcp!("\nrules\nrule t rtl last\n", CONTENT, "\nend\nend");
+    assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
+}
+
+#[test]
+fn test_rule_flags_repeat() {
+    const INPUT: &str = "aaae";
+    const CONTENT: &str = "a => e / _ e";
+    const EXPECT: &str = "eeee";
+    const RULE: &str = const_format::concatcp!("\nrules\nrule t repeat\n", CONTENT, "\nend\nend");
+    assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
+}
+
+#[test]
+fn test_rule_flags_repeat_first() {
+    const INPUT: &str = "tatata";
+    const CONTENT: &str = "a => e";
+    const EXPECT: &str = "tetete";
+    const RULE: &str =
+        const_format::concatcp!("\nrules\nrule t first repeat\n", CONTENT, "\nend\nend");
+    assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
+}
+
+#[test]
+fn test_rule_flags_sub() {
+    const INPUT: &str = "tata";
+    const CONTENT: &str = "a => e";
+    const EXPECT: &str = "tate";
+    const RULE: &str = const_format::concatcp!("\nrules\nsubx t last\n", CONTENT, "\nend\nend");
+    assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
+}
+
+#[test]
+fn test_rule_flags_repeat_infinite_loop() {
+    const RULE: &str = "\nrules\nrule t repeat\ni => a\ne => i\na => e\nend\nend";
+    let prog: Program = construct(&(create_ipa() + RULE)).unwrap();
+    let result = prog.apply(from_string(&prog, &String::from("ta")).unwrap());
+    match result {
+        Ok(_) => panic!(),
+        Err(v) => assert!(matches!(v, ApplicationError::InternalError(_))),
+    }
+}
+
+#[test]
+fn test_rule_flags_unknown_error() {
+    const PROG: &str = "rules\nrule t sideways\na => e\nend\nend";
+    let offset = create_ipa().split('\n').count() as u32;
+    let result = construct(&format!("{0}\n{1}", create_ipa(), PROG));
+    match result {
+        Ok(_) => panic!(),
+        Err(v) => assert!(
+            v.error_type == ConstructorErrorType::UnknownCommandError
+                && v.line_number_user_program == LineNumberInformation::Raw(offset + 2)
+        ),
+    }
+}
+
+#[test]
+fn test_rule_flags_conflict_error() {
+    const PROG: &str = "rules\nrule t first last\na => e\nend\nend";
+    let result = construct(&format!("{0}\n{1}", create_ipa(), PROG));
+    match result {
+        Ok(_) => panic!(),
+        Err(v) => assert!(v.error_type == ConstructorErrorType::MalformedDefinition),
+    }
+}
+
+#[test]
+fn test_persistent_rule() {
+    const INPUT: &str = "tat";
+    const CONTENT: &str = "rule cleanup persistent\nh => *\nend\nrule a\nt => h\nend";
+    const EXPECT: &str = "a";
+    const RULE: &str = const_format::concatcp!("\nrules\n", CONTENT, "\nend");
+    assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
+}
+
+#[test]
+fn test_persistent_sub() {
+    //The h made by rule b is only removed if the sub runs again after it
+    const INPUT: &str = "tat";
+    const CONTENT: &str =
+        "rule a\nt => d\nend\nsub cleanup persistent\nh => *\nend\nrule b\na => h\nend";
+    const EXPECT: &str = "dd";
+    const RULE: &str = const_format::concatcp!("\nrules\n", CONTENT, "\nend");
+    assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
+}
+
+#[test]
+fn test_persistent_rule_name_shared_with_sub() {
+    //The persistent rule doesn't replace the sub with the same name
+    const CONTENT: &str =
+        "sub cleanup\nh => x\nend\nrule cleanup persistent\na => e\nend\ncall cleanup";
+    const RULE: &str = const_format::concatcp!("\nrules\n", CONTENT, "\nend");
+    assert_eq!(simple_test_helper(RULE, "ha"), "xe");
+}
+
+#[test]
+fn test_persistent_in_sub_error() {
+    const RULES: &str = "\nrules\nsub s\nrule a persistent\na => e\nend\nend\nend";
+    let offset = create_ipa().split('\n').count() as u32;
+    let error = match construct(&(create_ipa() + RULES)) {
+        Ok(_) => panic!(),
+        Err(v) => v,
+    };
+    assert_eq!(error.error_type, ConstructorErrorType::MalformedDefinition);
+    assert_eq!(
+        error.line_number_user_program,
+        LineNumberInformation::Raw(offset + 3)
+    );
+}
+
+#[test]
+fn test_persistent_keeps_mod_flag() {
+    const INPUT: &str = "ha";
+    const CONTENT: &str = "sub cleanup persistent\nh => *\nend\nrule a\nq => k\nend\njmp skip mod\nrule b\na => e\nend\nlabel skip";
+    const EXPECT: &str = "e";
+    const RULE: &str = const_format::concatcp!("\nrules\n", CONTENT, "\nend");
+    assert_eq!(simple_test_helper(RULE, INPUT), EXPECT);
+}
+
+const SYLLABLES: &str =
+    "\nsyllables\nonset [consonant]<0:2>\nnucleus [vowel]\ncoda [consonant]<0:1>\nend";
+
+#[test]
+fn test_syllabify_auto() {
+    const RULE: &str = const_format::concatcp!(SYLLABLES, "\nrules\nrule t\nq => k\nend\nend");
+    assert_eq!(simple_test_helper(RULE, "pata"), "pa.ta");
+    assert_eq!(simple_test_helper(RULE, "pakta"), "pa.kta");
+    assert_eq!(simple_test_helper(RULE, "pa.ta.ka"), "pa.ta.ka");
+    assert_eq!(simple_test_helper(RULE, "ptk"), "ptk");
+}
+
+#[test]
+fn test_syllabify_prefers_valid_coda() {
+    const RULE: &str = "\nsyllables\nonset [consonant]<0:1>\nnucleus [vowel]\ncoda [consonant]<0:1>\nend\nrules\nrule t\nq => k\nend\nend";
+    assert_eq!(simple_test_helper(RULE, "pakta"), "pak.ta");
+    assert_eq!(simple_test_helper(RULE, "paa"), "pa.a");
+}
+
+#[test]
+fn test_syllabify_after_rule() {
+    const RULE: &str = const_format::concatcp!(SYLLABLES, "\nrules\nrule t\nt => *\nend\nend");
+    assert_eq!(simple_test_helper(RULE, "patak"), "pa.ak");
+}
+
+#[test]
+fn test_syllabify_manual() {
+    const HEADER: &str =
+        "\nsyllables manual\nonset [consonant]<0:2>\nnucleus [vowel]\ncoda [consonant]<0:1>\nend";
+    const RULE_A: &str = const_format::concatcp!(HEADER, "\nrules\nrule t\nq => k\nend\nend");
+    const RULE_B: &str = const_format::concatcp!(HEADER, "\nrules\nsyllabify\nend");
+    assert_eq!(simple_test_helper(RULE_A, "pata"), "pata");
+    assert_eq!(simple_test_helper(RULE_B, "pata"), "pa.ta");
+}
+
+#[test]
+fn test_syllabify_errors() {
+    let defs = create_ipa();
+    assert!(construct(&(defs.clone() + "\nrules\nsyllabify\nend")).is_err());
+    assert!(construct(&(defs.clone() + "\nsyllables\nonset [consonant]\nend")).is_err());
+    assert!(construct(&(defs.clone() + "\nsyllables\nnucleus $ [vowel]\nend")).is_err());
+    assert!(construct(&(defs + "\nsyllables\nnucleus [vowel]")).is_err());
+}
+
+#[test]
+fn test_syllable_boundary_enviorment() {
+    const RULE_A: &str = "\nrules\nrule t\nt => d / . _\nend\nend";
+    assert_eq!(simple_test_helper(RULE_A, "pa.ta"), "pa.da");
+    assert_eq!(simple_test_helper(RULE_A, "pata"), "pata");
+    assert_eq!(simple_test_helper(RULE_A, "ta"), "da");
+
+    const RULE_B: &str = "\nrules\nrule t\n[+voice] => [-voice] / _ .\nend\nend";
+    assert_eq!(simple_test_helper(RULE_B, "bad.ba"), "bat.ba");
+    assert_eq!(simple_test_helper(RULE_B, "ba.dba"), "ba.dba");
+    assert_eq!(simple_test_helper(RULE_B, "bad"), "bat");
+
+    const RULE_C: &str = "\nrules\nrule t\na => e / . [consonant] _\nend\nend";
+    assert_eq!(simple_test_helper(RULE_C, "pa.ta"), "pe.te");
+    assert_eq!(simple_test_helper(RULE_C, "pat.a"), "pet.a");
+}
+
+#[test]
+fn test_syllable_position_predicates() {
+    const RULE_A: &str = "\nrules\nrule t\na:final => e\nend\nend";
+    assert_eq!(simple_test_helper(RULE_A, "pa.ta"), "pa.te");
+    const RULE_B: &str = "\nrules\nrule t\na:initial => o\nend\nend";
+    assert_eq!(simple_test_helper(RULE_B, "pa.ta"), "po.ta");
+
+    const HEADER: &str =
+        "\nsyllables\nonset [consonant]<0:1>\nnucleus [vowel]\ncoda [consonant]<0:1>\nend";
+    const RULE_C: &str = const_format::concatcp!(
+        HEADER,
+        "\nrules\nrule t\n[+voice]:coda => [-voice]\nend\nend"
+    );
+    assert_eq!(simple_test_helper(RULE_C, "badba"), "bat.ba");
+    const RULE_D: &str =
+        const_format::concatcp!(HEADER, "\nrules\nrule t\na => e / _ :coda\nend\nend");
+    assert_eq!(simple_test_helper(RULE_D, "pakta"), "pek.ta");
+    const RULE_E: &str = const_format::concatcp!(
+        HEADER,
+        "\nrules\nrule t\n[vowel]:nucleus => [+long] / :onset _\nend\nend"
+    );
+    assert_eq!(simple_test_helper(RULE_E, "apa"), "a.paː");
+}
+
+#[test]
+fn test_syllable_position_errors() {
+    let defs = create_ipa();
+    assert!(construct(&(defs.clone() + "\nrules\nrule t\na:coda => e\nend\nend")).is_err());
+    assert!(construct(&(defs.clone() + "\nrules\nrule t\na:middle => e\nend\nend")).is_err());
+    assert!(construct(&(defs + "\nrules\nrule t\na => e / $ _ .\nend\nend")).is_ok());
+}
+
+#[test]
+fn test_suprasegmental_conversion() {
+    const RULE: &str = "\nrules\nrule t\nq => k\nend\nend";
+    assert_eq!(simple_test_helper(RULE, "ˈka.ta"), "ˈka.ta");
+    assert_eq!(simple_test_helper(RULE, "ka.ˌta"), "ka.ˌta");
+    assert_eq!(simple_test_helper(RULE, "kaˈta"), "ka.ˈta");
+    assert_eq!(simple_test_helper(RULE, "ma˥˩"), "ma˥˩");
+    assert_eq!(simple_test_helper(RULE, "ma51"), "ma˥˩");
+    assert_eq!(simple_test_helper(RULE, "ma˥ta˩"), "ma˥.ta˩");
+
+    let prog: Program = construct(&(create_ipa() + RULE)).unwrap();
+    assert!(from_string(&prog, &String::from("ma12345")).is_err());
+}
+
+#[test]
+fn test_suprasegmental_predicates() {
+    const RULE_A: &str = "\nrules\nrule t\na:stressed => e\nend\nend";
+    assert_eq!(simple_test_helper(RULE_A, "ˈka.ta"), "ˈke.ta");
+    const RULE_B: &str = "\nrules\nrule t\na:unstressed => e\nend\nend";
+    assert_eq!(simple_test_helper(RULE_B, "ˈka.ta"), "ˈka.te");
+    const RULE_C: &str = "\nrules\nrule t\na:51 => o\nend\nend";
+    assert_eq!(simple_test_helper(RULE_C, "ma˥˩.ta˧"), "mo˥˩.ta˧");
+    const RULE_D: &str = "\nrules\nrule t\n[consonant] => [+voice] / _ :ˈ\nend\nend";
+    assert_eq!(simple_test_helper(RULE_D, "ka.ˈta"), "ka.ˈda");
+}
+
+#[test]
+fn test_suprasegmental_results() {
+    const RULE_A: &str = "\nrules\nrule t\na => :stressed / _ $\nend\nend";
+    assert_eq!(simple_test_helper(RULE_A, "ka.ta"), "ka.ˈta");
+    const RULE_B: &str = "\nrules\nrule t\na:final => e:˥\nend\nend";
+    assert_eq!(simple_test_helper(RULE_B, "ka.ta"), "ka.te˥");
+    const RULE_C: &str = "\nrules\nrule t\n[vowel]:stressed => :unstressed\nend\nend";
+    assert_eq!(simple_test_helper(RULE_C, "ˈka.ta"), "ka.ta");
+}
+
+#[test]
+fn test_suprasegmental_kept_by_syllabify() {
+    const RULE: &str = const_format::concatcp!(SYLLABLES, "\nrules\nrule t\nt => *\nend\nend");
+    assert_eq!(simple_test_helper(RULE, "pa.ˈta"), "pa.ˈa");
+    assert_eq!(simple_test_helper(RULE, "ˈpa.ta˥"), "ˈpa.a˥");
+}
+
+#[test]
+fn test_suprasegmental_errors() {
+    let defs = create_ipa();
+    assert!(construct(&(defs.clone() + "\nrules\nrule t\na:7 => e\nend\nend")).is_err());
+    assert!(construct(&(defs + "\nrules\nrule t\na => e:12345\nend\nend")).is_err());
+}
+
 #[test]
 fn test_int_1() {
     let program = create_int_test_1();
@@ -940,10 +2123,10 @@ fn create_ipa() -> String {
 }
 
 fn random_letter() -> super::data::Letter {
-    let letter = random_u64();
+    let letter = random_value();
     super::data::Letter { value: letter }
 }
 
-fn random_u64() -> u64 {
+fn random_value() -> LetterValue {
     rand::thread_rng().gen()
 }
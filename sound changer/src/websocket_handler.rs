@@ -1,12 +1,12 @@
 use crate::manual_ux::rebuilder::rebuild;
 use crate::manual_ux::table::{self, Table};
-use crate::sc::constructor::construct;
+use crate::sc::constructor::construct_detached;
 use crate::sc::data::ConstructorError;
+use crate::sc::lint::LintWarning;
 use serde::{Deserialize, Serialize};
 
 use super::io::*;
-use super::sc::applicator::*;
-use super::sc::data::{to_string, ApplicationError, ThreadContext};
+use super::sc::data::{ApplicationError, DerivationStep, ThreadContext};
 
 #[derive(Deserialize, Debug)]
 pub enum WebSocketMessage {
@@ -25,9 +25,29 @@ pub enum WebSocketMessage {
     TryCompile {
         program: String,
     },
+    LintProgram {
+        program: String,
+    },
+    /// With `spelling`, the words are read and written in the program's spelling, using its
+    /// `deromanizer` and `romanizer` sections. The same goes for tracing and reversing.
     RunSC {
         program_name: String,
         to_convert: Vec<SCConversion>,
+        #[serde(default)]
+        spelling: bool,
+    },
+    TraceSC {
+        program_name: String,
+        word: String,
+        #[serde(default)]
+        spelling: bool,
+    },
+    ReverseSC {
+        program_name: String,
+        word: String,
+        max_candidates: usize,
+        #[serde(default)]
+        spelling: bool,
     },
     LoadTable {
         contents: String,
@@ -47,7 +67,10 @@ pub enum WebSocketResponse {
     RequestOverwrite,
     LoadFileResult { data: String },
     RunSCResult { to_convert: Vec<SCConversion> },
-    CompilationResult { result: Option<ConstructorError> },
+    TraceSCResult { steps: Vec<DerivationStep> },
+    ReverseSCResult { candidates: Vec<String> },
+    CompilationResult { errors: Vec<ConstructorError> },
+    LintResult { warnings: Vec<LintWarning> },
     TableResult { table: Option<Table> },
 }
 
@@ -80,10 +103,29 @@ impl WebSocketMessage {
                 vec![handle_load_program(name, contents, context)]
             }
             WebSocketMessage::TryCompile { program } => vec![handle_try_compilation(program)],
+            WebSocketMessage::LintProgram { program } => vec![handle_lint_program(program)],
             WebSocketMessage::RunSC {
                 program_name,
                 to_convert,
-            } => vec![handle_run_sc(program_name, to_convert, context)],
+                spelling,
+            } => vec![handle_run_sc(program_name, to_convert, *spelling, context)],
+            WebSocketMessage::TraceSC {
+                program_name,
+                word,
+                spelling,
+            } => vec![handle_trace_sc(program_name, word, *spelling, context)],
+            WebSocketMessage::ReverseSC {
+                program_name,
+                word,
+                max_candidates,
+                spelling,
+            } => vec![handle_reverse_sc(
+                program_name,
+                word,
+                *max_candidates,
+                *spelling,
+                context,
+            )],
             WebSocketMessage::Unknown { error } => vec![WebSocketResponse::Error {
                 message: format!("Unknown message, err: {}", error),
             }],
@@ -170,7 +212,8 @@ fn handle_load_program(
     contents: &str,
     context: &mut ThreadContext,
 ) -> WebSocketResponse {
-    let program = construct(contents);
+    //Programs from the editor aren't files, so they can't include anything
+    let program = construct_detached(contents);
     match program {
         Ok(v) => {
             if context.project.programs.contains_key(name) {
@@ -186,12 +229,17 @@ fn handle_load_program(
 }
 
 fn handle_try_compilation(program: &str) -> WebSocketResponse {
-    let result = construct(program);
+    let result = construct_detached(program);
     WebSocketResponse::CompilationResult {
-        result: match result {
-            Ok(_) => None,
-            Err(v) => Some(v),
-        },
+        errors: result.err().unwrap_or_default(),
+    }
+}
+
+/// Lints the program if it compiles, otherwise gives back the compilation errors.
+fn handle_lint_program(program: &str) -> WebSocketResponse {
+    match construct_detached(program) {
+        Ok(v) => WebSocketResponse::LintResult { warnings: v.lint() },
+        Err(errors) => WebSocketResponse::CompilationResult { errors },
     }
 }
 
@@ -209,6 +257,7 @@ fn send_error_response(error: (ApplicationError, usize, String), context: &mut T
 fn handle_run_sc(
     program_name: &String,
     to_convert: &Vec<SCConversion>,
+    spelling: bool,
     context: &mut ThreadContext,
 ) -> WebSocketResponse {
     if context.project.programs.contains_key(program_name) {
@@ -224,12 +273,11 @@ fn handle_run_sc(
                 continue;
             }
             let input = result[i].data.as_ref().unwrap();
-            match from_string(program, input) {
+            match program.read_word(input, spelling) {
                 Ok(val) => {
                     match program.apply(val) {
                         Ok(v) => {
-                            let output = to_string(program, v);
-                            result[i].data = output;
+                            result[i].data = program.write_word(v, spelling);
                         }
                         Err(v) => {
                             errors.push((v, i, input.clone()));
@@ -255,3 +303,71 @@ fn handle_run_sc(
         }
     }
 }
+
+fn handle_trace_sc(
+    program_name: &String,
+    word: &str,
+    spelling: bool,
+    context: &ThreadContext,
+) -> WebSocketResponse {
+    let program = match context.project.programs.get(program_name) {
+        Some(v) => v,
+        None => {
+            return WebSocketResponse::Error {
+                message: format!("Unknown program name \"{}\"", program_name),
+            }
+        }
+    };
+
+    let result = program
+        .read_word(word, spelling)
+        .and_then(|v| program.apply_traced(v));
+    match result {
+        Ok((_, steps)) => WebSocketResponse::TraceSCResult { steps },
+        Err(v) => WebSocketResponse::Error {
+            message: format!("Issue tracing word \"{}\", error: {}", word, v),
+        },
+    }
+}
+
+/// The most ancestor forms a client can ask for at once.
+const MAX_REVERSE_CANDIDATES: usize = 100;
+
+fn handle_reverse_sc(
+    program_name: &String,
+    word: &str,
+    max_candidates: usize,
+    spelling: bool,
+    context: &ThreadContext,
+) -> WebSocketResponse {
+    let program = match context.project.programs.get(program_name) {
+        Some(v) => v,
+        None => {
+            return WebSocketResponse::Error {
+                message: format!("Unknown program name \"{}\"", program_name),
+            }
+        }
+    };
+
+    let target = match program.read_word(word, spelling) {
+        Ok(v) => v,
+        Err(v) => {
+            return WebSocketResponse::Error {
+                message: format!("Issue reversing word \"{}\", error: {}", word, v),
+            }
+        }
+    };
+    let mut candidates: Vec<String> = Vec::new();
+    let max_candidates = max_candidates.min(MAX_REVERSE_CANDIDATES);
+    for v in program.apply_reverse(&target, max_candidates) {
+        match program.write_word(v, spelling) {
+            Ok(v) => candidates.push(v),
+            Err(v) => {
+                return WebSocketResponse::Error {
+                    message: format!("Issue reversing word \"{}\", error: {}", word, v),
+                }
+            }
+        }
+    }
+    WebSocketResponse::ReverseSCResult { candidates }
+}